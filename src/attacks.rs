@@ -0,0 +1,39 @@
+use crate::bitboard::Bitboard;
+
+// Squares reached from every square by steps of (files, ranks), the ones leaving the board dropped
+const fn leaper_table(steps: &[(i32, i32)]) -> [u64; 64] {
+    let mut table = [0; 64];
+    let mut index = 0;
+    while index < 64 {
+        let mut i = 0;
+        while i < steps.len() {
+            let file = (index % 8) as i32 + steps[i].0;
+            let rank = (index / 8) as i32 + steps[i].1;
+            if file >= 0 && file < 8 && rank >= 0 && rank < 8 {
+                table[index] |= 1 << (rank * 8 + file);
+            }
+            i += 1;
+        }
+        index += 1;
+    }
+    table
+}
+
+const KNIGHT_ATTACKS: [u64; 64] = leaper_table(&[(-2, -1), (-1, -2), (1, -2), (2, -1), (-2, 1), (-1, 2), (1, 2), (2, 1)]);
+const KING_ATTACKS: [u64; 64] = leaper_table(&[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)]);
+
+// Pawn captures indexed by the side, White pawns walk up the board and Black pawns down
+const PAWN_ATTACKS: [[u64; 64]; 2] = [leaper_table(&[(-1, 1), (1, 1)]), leaper_table(&[(-1, -1), (1, -1)])];
+
+pub fn knight_attacks(index: usize) -> Bitboard {
+    Bitboard{bits: KNIGHT_ATTACKS[index]}
+}
+
+pub fn king_attacks(index: usize) -> Bitboard {
+    Bitboard{bits: KING_ATTACKS[index]}
+}
+
+// Squares a pawn of `turn` standing on `index` attacks
+pub fn pawn_attacks(index: usize, turn: bool) -> Bitboard {
+    Bitboard{bits: PAWN_ATTACKS[turn as usize][index]}
+}
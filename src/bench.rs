@@ -0,0 +1,32 @@
+use std::time::Instant;
+
+use crate::game::{search_alpha_beta, SearchContext};
+use crate::position::Position;
+use crate::tt::{TranspositionTable, DEFAULT_MB};
+
+// Search the start position at every depth up to `max_depth` and report how fast the search goes
+pub fn run(max_depth: usize) {
+    let mut total_nodes = 0;
+    let mut total_seconds = 0.0;
+
+    for depth in 1..=max_depth {
+        let mut position = Position::new();
+        let mut tt = TranspositionTable::new(DEFAULT_MB);
+        let mut context = SearchContext::new(&[position.key()], &mut tt);
+
+        let start = Instant::now();
+        let best_move = search_alpha_beta(&mut position, &mut context, depth);
+        let seconds = start.elapsed().as_secs_f64();
+
+        total_nodes += context.nodes;
+        total_seconds += seconds;
+
+        match best_move {
+            Some(m) => print!("depth {} best {} ", depth, m),
+            None    => print!("depth {} best none ", depth),
+        }
+        println!("nodes {} time {:.3}s nps {:.0}", context.nodes, seconds, context.nodes as f64 / seconds);
+    }
+
+    println!("total nodes {} time {:.3}s nps {:.0}", total_nodes, total_seconds, total_nodes as f64 / total_seconds);
+}
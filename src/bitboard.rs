@@ -1,7 +1,9 @@
 use crate::pieces::Piece;
+use crate::magic::{bishop_attacks, rook_attacks, queen_attacks};
+use crate::attacks::{knight_attacks, king_attacks, pawn_attacks};
 use Piece::*;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Bitboard {
     pub bits : u64,
 }
@@ -39,15 +41,6 @@ impl Bitboard {
         self.add_piece(to_index);
     }
 
-    pub fn mirror(&self) -> Bitboard {
-        let mut mirrored_bits = 0;
-        for row in 0..8 {
-            let row_bits = (self.bits >> (row * 8)) & 0xFF;
-            mirrored_bits |= (row_bits as u64) << ((7 - row) * 8);
-        }
-        Bitboard { bits: mirrored_bits }
-    }
-
     pub fn combine_bitboards(bitboards: &[Bitboard]) -> Bitboard {
         let mut combined_bits = 0;
         for &bitboard in bitboards {
@@ -63,14 +56,10 @@ impl Bitboard {
     pub fn get_indices(&self) -> Vec<usize> {
         let mut indices = Vec::new();
         let mut tmp: Bitboard = Bitboard{bits:self.bits};
-        let mut index = 0;
 
-        while index < 64 && tmp.bits != 0 {
-            if self.get_bit(index) == 1 {
-                indices.push(index);
-                tmp.bits &= tmp.bits-1;
-            }
-            index += 1;
+        while tmp.bits != 0 {
+            indices.push(tmp.bits.trailing_zeros() as usize);
+            tmp.bits &= tmp.bits-1;
         }
         indices
     }
@@ -79,86 +68,54 @@ impl Bitboard {
         self.bits.count_ones()
     }
 
-    pub fn moves(&self, index : usize, opponent: Bitboard, piece : Piece, last_opponent_move : &Option<(usize, usize)>, castle : bool) -> Vec<usize> {
-        match piece {
-            Pawn   => self.pawn_moves  (index, opponent, last_opponent_move),
-            Knight => self.knight_moves(index),
-            Bishop => self.bishop_moves(index, opponent),
-            Rook   => self.rook_moves  (index, opponent),
-            Queen  => self.queen_moves (index, opponent),
-            King   => self.king_moves  (index, opponent, castle),
-        }
+    // Destinations of `piece` standing on `index` for the side `turn`, false being White
+    pub fn moves(&self, index : usize, opponent: Bitboard, piece : Piece, en_passant : &Option<usize>, turn: bool) -> Bitboard {
+        let occupied = Bitboard{bits: self.bits | opponent.bits};
+        let attacks = match piece {
+            Pawn   => return self.pawn_moves(index, opponent, en_passant, turn),
+            Knight => knight_attacks(index),
+            Bishop => bishop_attacks(index, occupied),
+            Rook   => rook_attacks  (index, occupied),
+            Queen  => queen_attacks (index, occupied),
+            King   => king_attacks  (index),
+        };
+        Bitboard{bits: attacks.bits & !self.bits}
     }
 
-    pub fn pawn_moves(&self, index: usize, opponent: Bitboard, last_opponent_move : &Option<(usize, usize)>) -> Vec<usize> {
-        let mut moves = Vec::new();
-        let mut forward_one = index + 8;
-        let mut forward_two = index + 16;
-        let mut capture_left = index + 7;
-        let mut capture_right = index + 9;
+    pub fn pawn_moves(&self, index: usize, opponent: Bitboard, en_passant : &Option<usize>, turn: bool) -> Bitboard {
+        let occupied = Bitboard{bits: self.bits | opponent.bits};
+        let (forward_one, forward_two, start_rank) = if !turn {(index + 8, index + 16, 1)} else {(index - 8, index.wrapping_sub(16), 6)};
 
-        if let Some((from, to)) = *last_opponent_move {
-            // En passant
-            if (to >= from && to - from == 16) || (to < from && from - to == 16) {
-                if index + 1 == to || index - 1 == to {
-                    if self.get_bit(to) == 0 {
-                        moves.push(to + 8);
-                    }
-                }
-            }
-        }
+        // Captures, the en passant target being empty
+        let en_passant = en_passant.map_or(0, |target| 1 << target);
+        let mut moves = Bitboard{bits: pawn_attacks(index, turn).bits & (opponent.bits | en_passant)};
 
-        
-        if self.get_bit(forward_one) == 0 && opponent.get_bit(forward_one) == 0 {
-            moves.push(forward_one);
+        if occupied.get_bit(forward_one) == 0 {
+            moves.add_piece(forward_one);
             // Check if the pawn is in its starting position and can move two squares forward
-            if index < 16 && self.get_bit(forward_two) == 0 && opponent.get_bit(forward_two) == 0{
-                moves.push(forward_two);
+            if index / 8 == start_rank && occupied.get_bit(forward_two) == 0 {
+                moves.add_piece(forward_two);
             }
         }
 
-        // Check if the pawn can capture diagonally to the left
-        if index % 8 != 0 && opponent.get_bit(capture_left) == 1 {
-            moves.push(capture_left);
-        }
-
-        // Check if the pawn can capture diagonally to the right
-        if index % 8 != 7 && opponent.get_bit(capture_right) == 1 {
-            moves.push(capture_right);
-        }
-
         moves
     }
 
-    pub fn knight_moves(&self, index: usize) -> Vec<usize> {
-        let mut moves = Vec::new();
-        let offsets = [-17, -15, -10, -6, 6, 10, 15, 17];
-
-        for &offset in &offsets {
-            let dest = (index as isize + offset) as usize;
-            let dx = (index % 8) as isize - (dest % 8) as isize;
-            if dest < 64 && dx.abs() <= 2 && self.get_bit(dest) == 0{
-                moves.push(dest);
-            }
-        }
-
-        moves
-    }
-
-    pub fn bishop_moves(&self, index: usize, opponent: Bitboard) -> Vec<usize> {
+    // Walk the rays square by square, too slow for the search:
+    // only the reference the magic tables of magic.rs are built from
+    pub fn bishop_rays(&self, index: usize, opponent: Bitboard) -> Vec<usize> {
         let mut moves = Vec::new();
         let directions = [-9, -7, 7, 9];
     
         for &dir in &directions {
             let mut dest = (index as isize) + dir;
-            while dest >= 0 && dest < 64 {
+            while (0..64).contains(&dest) {
 
-                let src_col  = index % 8;
+                // Each step goes one file over from the previous square, anything else wrapped around the board
+                let prev_col = (dest - dir) as usize % 8;
                 let dest_col = dest as usize % 8;
-                let src_lin  = index / 8;
-                let dest_lin = dest as usize / 8;
 
-                if ((dest_col >= src_col && (dir == 9 || dir == -7)) || (dest_col <= src_col && (dir == -9 || dir == 7))) && src_lin != dest_lin {
+                if (dest_col == prev_col + 1 && (dir == 9 || dir == -7)) || (dest_col + 1 == prev_col && (dir == -9 || dir == 7)) {
                     if self.get_bit(dest as usize) == 1 {
                         break;
                     }
@@ -176,13 +133,13 @@ impl Bitboard {
         moves
     }
     
-    pub fn rook_moves(&self, index: usize, opponent: Bitboard) -> Vec<usize> {
+    pub fn rook_rays(&self, index: usize, opponent: Bitboard) -> Vec<usize> {
         let mut moves = Vec::new();
         let directions = [-8, -1, 1, 8];
     
         for &dir in &directions {
             let mut dest = (index as isize) + dir;
-            while dest >= 0 && dest < 64 {
+            while (0..64).contains(&dest) {
                 let src_col = index % 8;
                 let dest_col = dest as usize % 8;
                 if (dest_col > src_col && dir == 1) || (dest_col < src_col && dir == -1) || (dir.abs() > 1) {
@@ -204,38 +161,6 @@ impl Bitboard {
     }
     
 
-    pub fn queen_moves(&self, index: usize, opponent: Bitboard) -> Vec<usize> {
-        let mut moves = self.bishop_moves(index, opponent);
-        moves.extend(self.rook_moves(index, opponent));
-        moves
-    }
-
-    pub fn king_moves(&self, index: usize, opponent: Bitboard, castle : bool) -> Vec<usize> {
-        let mut moves = Vec::new();
-        let offsets = [-9, -8, -7, -1, 1, 7, 8, 9];
-
-        let castle_right: u64 = 0b0000000000000000000000000000000000000000000000000000000001100000;
-        let castle_left : u64 = 0b0000000000000000000000000000000000000000000000000000000000001110;
-
-        if castle && Bitboard::combine_bitboards(&[*self, opponent]).bits & castle_right == 0 {
-            moves.push(6);
-        } 
-        
-        if castle && Bitboard::combine_bitboards(&[*self, opponent]).bits & castle_left == 0 {
-            moves.push(2);
-        }
-
-        for &offset in &offsets {
-            let dest = (index as isize + offset) as usize;
-            let dx = (index % 8) as isize - (dest % 8) as isize;
-            if dest < 64 && dx.abs() <= 1 && self.get_bit(dest) == 0 {
-                moves.push(dest);
-            }
-        }
-
-        moves
-    }
-
     pub fn evaluate_pawn_structure(&self) -> isize {
         let mut score = 0;
 
@@ -244,8 +169,8 @@ impl Bitboard {
         let mut current_file_has_pawn:bool;
         let mut pawn_islands = 0;
 
-        for file in 0..8 {
-            current_file_has_pawn = (self.bits & FILE_MASKS[file]) != 0;
+        for file_mask in FILE_MASKS {
+            current_file_has_pawn = (self.bits & file_mask) != 0;
             if current_file_has_pawn && !previous_file_has_pawn {
                 pawn_islands += 1;
             }
@@ -267,12 +192,12 @@ impl Bitboard {
         }
 
         // Evaluate isolated pawns
-        for file in 0..8 {
+        for (file, &file_mask) in FILE_MASKS.iter().enumerate() {
             let left_adjacent_file = if file > 0 { file - 1 } else { file };
             let right_adjacent_file = if file < 7 { file + 1 } else { file };
             let left_mask = FILE_MASKS[left_adjacent_file];
             let right_mask = FILE_MASKS[right_adjacent_file];
-            let file_mask = FILE_MASKS[file];
+
 
             let isolated_pawn_mask = file_mask & !(left_mask | right_mask);
             if (self.bits & isolated_pawn_mask) != 0 {
@@ -0,0 +1,281 @@
+use std::fmt;
+
+use crate::bitboard::Bitboard;
+use crate::game::{algebraic_to_index, index_to_algebraic, Color};
+use crate::pieces::Piece;
+use crate::position::*;
+
+use Piece::*;
+
+use strum::EnumCount;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Why a FEN record could not be turned into a Position
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FenError {
+    MissingField(&'static str),
+    TooManyFields,
+    // Rank, from 1 to 8, that does not describe exactly 8 squares
+    BadRank(usize),
+    BadPiece(char),
+    BadSideToMove(String),
+    BadCastling(String),
+    BadEnPassant(String),
+    BadCounter(String),
+    // Each side needs exactly one king
+    KingCount(Color),
+    TooManyPieces(Color),
+    PawnOnLastRank,
+    // The side that just moved cannot have left its king in check
+    OpponentInCheck,
+    // A castling right whose king or rook is not on its starting square
+    CastlingWithoutPieces(char),
+    // An en passant target with no pawn that just jumped over it
+    ImpossibleEnPassant(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::MissingField(field)          => write!(f, "missing {} field", field),
+            FenError::TooManyFields                => write!(f, "too many fields"),
+            FenError::BadRank(rank)                => write!(f, "rank {} does not have 8 squares", rank),
+            FenError::BadPiece(c)                  => write!(f, "unknown piece '{}'", c),
+            FenError::BadSideToMove(side)          => write!(f, "side to move must be w or b, not '{}'", side),
+            FenError::BadCastling(castling)        => write!(f, "invalid castling rights '{}'", castling),
+            FenError::BadEnPassant(square)         => write!(f, "invalid en passant square '{}'", square),
+            FenError::BadCounter(counter)          => write!(f, "invalid move counter '{}'", counter),
+            FenError::KingCount(color)             => write!(f, "{:?} must have exactly one king", color),
+            FenError::TooManyPieces(color)         => write!(f, "{:?} has more pieces than a game can give", color),
+            FenError::PawnOnLastRank               => write!(f, "pawns cannot stand on the first or last rank"),
+            FenError::OpponentInCheck              => write!(f, "the side not to move is in check"),
+            FenError::CastlingWithoutPieces(right) => write!(f, "castling right '{}' without the king and rook at home", right),
+            FenError::ImpossibleEnPassant(square)  => write!(f, "no pawn can have just jumped over {}", square),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+// Position described by a FEN record.
+// The two move counters may be left out, as in EPD, they then default to 0 and 1.
+pub fn parse(fen: &str) -> Result<Position, FenError> {
+    let mut fields = fen.split_whitespace();
+    let placement  = fields.next().ok_or(FenError::MissingField("piece placement"))?;
+    let side       = fields.next().ok_or(FenError::MissingField("side to move"))?;
+    let castling   = fields.next().ok_or(FenError::MissingField("castling"))?;
+    let en_passant = fields.next().ok_or(FenError::MissingField("en passant"))?;
+    let counter = |field: Option<&str>, default: usize| match field {
+        Some(n) => n.parse().map_err(|_| FenError::BadCounter(n.to_string())),
+        None    => Ok(default),
+    };
+    let halfmove_clock = counter(fields.next(), 0)?;
+    let fullmove_number = counter(fields.next(), 1)?;
+    if fields.next().is_some() {
+        return Err(FenError::TooManyFields);
+    }
+    if fullmove_number == 0 {
+        return Err(FenError::BadCounter("0".to_string()));
+    }
+
+    // Ranks are listed from the 8th down to the 1st
+    let mut bitboards = [Bitboard{bits:0}; Piece::COUNT*Color::COUNT];
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::MissingField("rank"));
+    }
+    for (i, rank) in ranks.iter().enumerate() {
+        let mut file = 0;
+        for c in rank.chars() {
+            if let Some(empty) = c.to_digit(10) {
+                if !(1..=8).contains(&empty) {
+                    return Err(FenError::BadRank(8 - i));
+                }
+                file += empty as usize;
+                continue;
+            }
+            // Knights are always N in FEN, H is only a letter of the board display
+            let piece = Piece::from_char(c).filter(|_| c != 'h' && c != 'H').ok_or(FenError::BadPiece(c))?;
+            if file > 7 {
+                return Err(FenError::BadRank(8 - i));
+            }
+            bitboards[piece as usize + if c.is_uppercase() {0} else {Piece::COUNT}].add_piece((7 - i) * 8 + file);
+            file += 1;
+        }
+        if file != 8 {
+            return Err(FenError::BadRank(8 - i));
+        }
+    }
+
+    let turn = match side {
+        "w" => false,
+        "b" => true,
+        _   => return Err(FenError::BadSideToMove(side.to_string())),
+    };
+
+    let mut castle = [[false; 2]; Color::COUNT];
+    if castling != "-" {
+        for c in castling.chars() {
+            let (color, side) = match c {
+                'K' => (Color::White, KINGSIDE),
+                'Q' => (Color::White, QUEENSIDE),
+                'k' => (Color::Black, KINGSIDE),
+                'q' => (Color::Black, QUEENSIDE),
+                _   => return Err(FenError::BadCastling(castling.to_string())),
+            };
+            if castle[color as usize][side] {
+                return Err(FenError::BadCastling(castling.to_string()));
+            }
+            castle[color as usize][side] = true;
+        }
+    }
+
+    let en_passant = match en_passant {
+        "-"    => None,
+        square => Some(algebraic_to_index(&square.to_uppercase()).ok_or(FenError::BadEnPassant(square.to_string()))?),
+    };
+
+    let mut position = Position {bitboards, turn, castle, en_passant, halfmove_clock, fullmove_number, hash: 0};
+    validate(&position)?;
+    position.hash = position.compute_hash();
+    Ok(position)
+}
+
+// Reject what no sequence of legal moves can reach in the obvious ways
+fn validate(position: &Position) -> Result<(), FenError> {
+    for (color, turn) in [(Color::White, false), (Color::Black, true)] {
+        if position.pieces(King, turn).count_bits() != 1 {
+            return Err(FenError::KingCount(color));
+        }
+        let (player, _) = position.get_player_and_opponent_bitboards(turn);
+        if player.count_bits() > 16 || position.pieces(Pawn, turn).count_bits() > 8 {
+            return Err(FenError::TooManyPieces(color));
+        }
+        if position.pieces(Pawn, turn).bits & 0xFF000000000000FF != 0 {
+            return Err(FenError::PawnOnLastRank);
+        }
+    }
+
+    if position.is_king_attacked(!position.turn) {
+        return Err(FenError::OpponentInCheck);
+    }
+
+    for (turn, rights) in [(false, ['K', 'Q']), (true, ['k', 'q'])] {
+        let home = if !turn {0} else {56};
+        for (side, rook) in [(KINGSIDE, home + 7), (QUEENSIDE, home)] {
+            if position.castle[turn as usize][side]
+                && (position.pieces(King, turn).get_bit(home + 4) == 0 || position.pieces(Rook, turn).get_bit(rook) == 0) {
+                return Err(FenError::CastlingWithoutPieces(rights[side]));
+            }
+        }
+    }
+
+    // The pawn that jumped belongs to the side that just moved and stands right past the target, which it left empty
+    if let Some(target) = position.en_passant {
+        let impossible = FenError::ImpossibleEnPassant(index_to_algebraic(target).to_lowercase());
+        if target / 8 != if !position.turn {5} else {2} {
+            return Err(impossible);
+        }
+        let (pawn, start) = if !position.turn {(target - 8, target + 8)} else {(target + 8, target - 8)};
+        let (player, opponent) = position.get_player_and_opponent_bitboards(position.turn);
+        if position.pieces(Pawn, !position.turn).get_bit(pawn) == 0 || (player.bits | opponent.bits) & (1 << target | 1 << start) != 0 {
+            return Err(impossible);
+        }
+    }
+
+    Ok(())
+}
+
+// FEN record of `position`
+pub fn export(position: &Position) -> String {
+    let mut placement = String::new();
+    for rank in (0..8).rev() {
+        let mut empty = 0;
+        for file in 0..8 {
+            let index = rank * 8 + file;
+            let piece = [false, true].into_iter().find_map(|turn| position.piece_at(turn, index).map(|piece| (piece, turn)));
+            match piece {
+                Some((piece, turn)) => {
+                    if empty > 0 {
+                        placement.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    placement.push(if !turn {piece.letter()} else {piece.letter().to_ascii_lowercase()});
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            placement.push_str(&empty.to_string());
+        }
+        if rank > 0 {
+            placement.push('/');
+        }
+    }
+
+    let mut castling: String = [(false, KINGSIDE, 'K'), (false, QUEENSIDE, 'Q'), (true, KINGSIDE, 'k'), (true, QUEENSIDE, 'q')]
+        .iter()
+        .filter(|&&(turn, side, _)| position.castle[turn as usize][side])
+        .map(|&(_, _, c)| c)
+        .collect();
+    if castling.is_empty() {
+        castling.push('-');
+    }
+
+    let en_passant = match position.en_passant {
+        Some(target) => index_to_algebraic(target).to_lowercase(),
+        None         => "-".to_string(),
+    };
+
+    format!("{} {} {} {} {} {}", placement, if position.turn {'b'} else {'w'}, castling, en_passant, position.halfmove_clock, position.fullmove_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_each_kind_of_bad_record() {
+        let cases = [
+            ("8/8/8/8/8/8/8/K6k",                                         FenError::MissingField("side to move")),
+            ("8/8/8/8/8/8/K6k w - -",                                     FenError::MissingField("rank")),
+            (&format!("{} extra", START_FEN),                             FenError::TooManyFields),
+            ("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",   FenError::BadRank(7)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",  FenError::BadPiece('X')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",  FenError::BadSideToMove("x".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KKq - 0 1",   FenError::BadCastling("KKq".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq j9 0 1", FenError::BadEnPassant("j9".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",  FenError::BadCounter("x".to_string())),
+            ("8/8/8/8/8/8/8/7k w - - 0 1",                                FenError::KingCount(Color::White)),
+            ("4k3/8/8/8/8/P7/PPPPPPPP/4K3 w - - 0 1",                     FenError::TooManyPieces(Color::White)),
+            ("4k3/8/8/8/8/8/8/P3K3 w - - 0 1",                            FenError::PawnOnLastRank),
+            ("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1",                           FenError::OpponentInCheck),
+            ("4k2r/8/8/8/8/8/8/4K3 w q - 0 1",                            FenError::CastlingWithoutPieces('q')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq e3 0 1", FenError::ImpossibleEnPassant("e3".to_string())),
+        ];
+        for (fen, error) in cases {
+            assert_eq!(parse(fen).err(), Some(error), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn exported_records_match_the_parsed_ones() {
+        for fen in [
+            START_FEN,
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 2",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Qk - 12 40",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+        ] {
+            let position = parse(fen).unwrap();
+            assert_eq!(export(&position), fen);
+            assert_eq!(parse(&export(&position)).unwrap().hash, position.hash);
+        }
+    }
+
+    #[test]
+    fn counters_default_when_left_out() {
+        assert_eq!(export(&parse("4k3/8/8/8/8/8/8/4K3 b - -").unwrap()), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+    }
+}
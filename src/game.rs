@@ -2,524 +2,662 @@ use crate::bitboard::Bitboard;
 use crate::pieces::Piece;
 use crate::pieces::MAP;
 use crate::color::*;
-use std::collections::btree_map::Values;
+use crate::position::*;
+use crate::moves::Move;
+use crate::fen;
+use crate::san::{parse_move, to_san};
+use crate::tt::{Bound, TranspositionTable};
+use crate::ordering::MoveOrdering;
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use Piece::*;
 
 use strum::EnumCount;
 use strum_macros::{EnumCount as EnumCountMacro, EnumIter};
 
-const ORDER: [usize; Piece::COUNT] = [Pawn as usize, Knight as usize, Bishop as usize, Rook as usize,  Queen as usize, King as usize]; 
-
-
-#[derive(Debug, EnumCountMacro, EnumIter)]
+#[derive(Debug, EnumCountMacro, EnumIter, PartialEq, Eq, Copy, Clone)]
 pub enum Color {
     White = 0,
     Black = 1
 }
 
-pub fn algebraic_to_index(algebraic: &str, inverted: bool) -> Option<usize> {
+// Score of a mate found at the root, reduced by the distance to it so faster mates are preferred
+pub const MATE_SCORE: isize = 100_000;
+
+// Deepest search think can be asked for
+pub const MAX_DEPTH: usize = 64;
+
+// Above any score a search can return
+const INFINITY: isize = MATE_SCORE + 1;
+
+// Most a capture can change the score besides the captured piece, captures that cannot
+// bring the score back into the window even with it are not searched by the quiescence search
+const DELTA_MARGIN: isize = 200;
+
+// Half width of the first aspiration window around the score of the last depth
+const ASPIRATION_WINDOW: isize = 50;
+
+// Selective search: how many plies from the leaves each technique applies, and the margins, per ply, it leaves the static score
+const REVERSE_FUTILITY_DEPTH: usize = 3;
+const REVERSE_FUTILITY_MARGIN: isize = 150;
+const RAZORING_DEPTH: usize = 2;
+const RAZORING_MARGIN: isize = 300;
+const FUTILITY_DEPTH: usize = 2;
+const FUTILITY_MARGIN: isize = 200;
+// Null moves are tried from this many plies from the leaves
+const NULL_MOVE_DEPTH: usize = 3;
+// Late move reductions start with this move of a node, this many plies from the leaves
+const LATE_MOVE: usize = 3;
+const LATE_MOVE_DEPTH: usize = 3;
+
+// Selective search techniques, each can be turned off to measure what it brings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pruning {
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility: bool,
+    pub futility: bool,
+    pub razoring: bool,
+}
+
+impl Default for Pruning {
+    fn default() -> Self {
+        Pruning {null_move: true, late_move_reductions: true, reverse_futility: true, futility: true, razoring: true}
+    }
+}
+
+impl Pruning {
+    // Names the protocols give the switches
+    pub const NAMES: [&'static str; 5] = ["NullMove", "LateMoveReductions", "ReverseFutility", "Futility", "Razoring"];
+
+    // Switch named `name`, in any case
+    pub fn switch(&mut self, name: &str) -> Option<&mut bool> {
+        match name.to_lowercase().as_str() {
+            "nullmove"           => Some(&mut self.null_move),
+            "latemovereductions" => Some(&mut self.late_move_reductions),
+            "reversefutility"    => Some(&mut self.reverse_futility),
+            "futility"           => Some(&mut self.futility),
+            "razoring"           => Some(&mut self.razoring),
+            _                    => None,
+        }
+    }
+}
+
+// Square index of a coordinate like E4, from A1 = 0 to H8 = 63
+pub fn algebraic_to_index(algebraic: &str) -> Option<usize> {
     if algebraic.len() != 2 {
         return None;
     }
-    let file = algebraic.chars().nth(0)?;
+    let file = algebraic.chars().next()?;
     let rank = algebraic.chars().nth(1)?;
-    if file < 'A' || file > 'H' || rank < '1' || rank > '8' {
+    if !('A'..='H').contains(&file) || !('1'..='8').contains(&rank) {
         return None;
     }
     let file_index = file as usize - 'A' as usize;
-    let mut rank_index = rank as usize - '1' as usize;
-    rank_index = if inverted {7 - rank_index} else {rank_index};
+    let rank_index = rank as usize - '1' as usize;
     Some(rank_index * 8 + file_index)
 }
 
-pub fn index_to_algebraic(index: usize, inverted: bool) -> String {
+pub fn index_to_algebraic(index: usize) -> String {
     let file_index = index % 8;
-    let mut rank_index = index / 8;
-    rank_index = if inverted {7 - rank_index} else {rank_index};
+    let rank_index = index / 8;
 
-    let file = ('A' as u8 + file_index as u8) as char;
-    let rank = ('1' as u8 + rank_index as u8) as char;
+    let file = (b'A' + file_index as u8) as char;
+    let rank = (b'1' + rank_index as u8) as char;
 
     format!("{}{}", file, rank)
 }
 
-pub fn invert_index(index:usize) -> usize {
-    let file_index = index % 8;
-    let mut rank_index = index / 8;
-    rank_index = 7 - rank_index;
-    rank_index * 8 + file_index
-}
-
-pub fn get_bitboards() -> [Bitboard; Piece::COUNT*Color::COUNT] {
-    let mut bitboards: [Bitboard; Piece::COUNT*Color::COUNT] = [Bitboard{bits:0}; Piece::COUNT*Color::COUNT];
-
-    bitboards[Pawn as usize].bits                  = 0b0000000000000000000000000000000000000000000000001111111100000000;
-    bitboards[(Pawn as usize)+Piece::COUNT].bits   = bitboards[Pawn as usize].bits;
-
-    bitboards[Rook as usize].bits                  = 0b0000000000000000000000000000000000000000000000000000000010000001;
-    bitboards[(Rook as usize)+Piece::COUNT].bits   = bitboards[Rook as usize].bits;
-
-    bitboards[Knight as usize].bits                = 0b0000000000000000000000000000000000000000000000000000000001000010;
-    bitboards[(Knight as usize)+Piece::COUNT].bits = bitboards[Knight as usize].bits ;
-
-    bitboards[Bishop as usize].bits                = 0b0000000000000000000000000000000000000000000000000000000000100100;
-    bitboards[(Bishop as usize)+Piece::COUNT].bits = bitboards[Bishop as usize].bits;
-
-    bitboards[Queen as usize].bits                 = 0b0000000000000000000000000000000000000000000000000000000000001000;
-    bitboards[(Queen as usize)+Piece::COUNT].bits  = bitboards[Queen as usize].bits;
-
-    bitboards[King as usize].bits                  = 0b0000000000000000000000000000000000000000000000000000000000010000;
-    bitboards[(King as usize)+Piece::COUNT].bits   = bitboards[King as usize].bits;
-
-    bitboards
-}
-
-pub fn get_input() -> String {
+// A line typed by the player, None once the input is closed
+pub fn get_input() -> Option<String> {
     let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("Failed to read line");
-    input.trim().to_string()
+    match io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_)          => Some(input.trim().to_string()),
+    }
 }
 
-pub fn get_player_piece_input(turn: bool) -> String {
+pub fn get_player_input(turn: bool) -> Option<String> {
     if !turn {
-        print!("{}Red{} play.\n", RED, RESET);
+        println!("{}Red{} play.", RED, RESET);
     } else {
-        print!("{}Blue{} play.\n", BLUE, RESET);
+        println!("{}Blue{} play.", BLUE, RESET);
     }
-    println!("Enter the piece you want to move:");
+    println!("Enter your move (like Nf3, exd5, O-O or e2e4):");
     get_input()
 }
 
-pub fn get_player_move_input(turn: bool, moves: &Vec<usize>) -> String {
-    print!("Possible moves : ");
-    for &m in moves {
-        print!("{} ",  index_to_algebraic(m, turn));
+// Print the status of the game for the side `turn`, return true if the game is over
+pub fn announce_game_status(status: GameStatus, turn: bool) -> bool {
+    match status {
+        GameStatus::Ongoing   => false,
+        GameStatus::Check     => {
+            println!("Check!");
+            false
+        },
+        GameStatus::Checkmate => {
+            if turn {println!("Checkmate, {}Red{} wins!", RED, RESET);}
+            else    {println!("Checkmate, {}Blue{} wins!", BLUE, RESET);}
+            true
+        },
+        GameStatus::Stalemate => {
+            println!("Stalemate, it's a draw!");
+            true
+        },
+        GameStatus::Draw(reason) => {
+            announce_draw(reason);
+            true
+        },
     }
-    print!("\n");
-    println!("Enter your move :");
-    get_input()
 }
 
-
-pub fn get_player_and_opponent_bitboards(bitboards: &[Bitboard; Piece::COUNT*Color::COUNT], turn: bool) -> (Bitboard, Bitboard) {
-    let mut player = Bitboard{bits:0};
-    let mut opponent = Bitboard{bits:0};
-
-    for i in 0..Piece::COUNT {
-        player.combine_bitboard(bitboards[i + if !turn {0} else {Piece::COUNT}]);
-        opponent.combine_bitboard(bitboards[i + if turn {0} else {Piece::COUNT}]);
-    } 
-    (player, opponent)
+pub fn announce_draw(reason: DrawReason) {
+    match reason {
+        DrawReason::FiftyMoveRule        => println!("Draw by the fifty-move rule!"),
+        DrawReason::SeventyFiveMoveRule  => println!("Draw by the seventy-five-move rule!"),
+        DrawReason::ThreefoldRepetition  => println!("Draw by threefold repetition!"),
+        DrawReason::FivefoldRepetition   => println!("Draw by fivefold repetition!"),
+        DrawReason::InsufficientMaterial => println!("Draw by insufficient material!"),
+    }
 }
 
-pub fn get_piece_index_to_move(bitboards: &[Bitboard; Piece::COUNT*Color::COUNT], turn: bool, index: usize) -> Option<usize> {
-    for i in 0..Piece::COUNT {
-        if bitboards[i + if !turn {0} else {Piece::COUNT}].get_bit(index) == 1 {
-            return Some(i);
+// Let the human play a move and return it, None once the input is closed
+pub fn play_player(position: &mut Position) -> Option<Move> {
+    loop {
+        let input = get_player_input(position.turn)?;
+        match parse_move(position, &input) {
+            Ok(m) => {
+                position.make_move(m);
+                return Some(m);
+            }
+            Err(error) => {
+                println!("{}", error);
+                let legal: Vec<String> = position.legal_moves().into_iter().map(|m| to_san(position, m)).collect();
+                println!("Legal moves: {}", legal.join(" "));
+            }
         }
     }
-    None
 }
 
-pub fn check_win_conditions(bitboards: &[Bitboard; Piece::COUNT*Color::COUNT], win: &mut bool, print: bool) {
-    if bitboards[Piece::King as usize].bits == 0 {
-        if print {println!("{}Blue{} wins!", BLUE, RESET);}
-        *win = true;
-    }
-    if bitboards[(Piece::King as usize) + Piece::COUNT].bits == 0 {
-        if print {println!("{}Red{} wins!", RED, RESET);}
-        *win = true;
-    }
+// What every node of a search shares
+pub struct SearchContext<'a> {
+    // Positions since the last capture or pawn move, the one being searched last
+    pub history: Vec<PositionKey>,
+    pub nodes: u64,
+    // Raised from outside to end the search, or by the search itself once past the deadline
+    pub stop: Arc<AtomicBool>,
+    pub deadline: Option<Instant>,
+    // Score of the best move found at the root, for the side to move
+    pub score: isize,
+    // Best move of the previous iteration, searched first at the root
+    pub root_move: Option<Move>,
+    // Moves from the root to the position being searched, None for a null move
+    pub line: Vec<Option<Move>>,
+    // Principal variation: the best move at the root and the best answers to it, as far as they were searched
+    pub pv: Vec<Move>,
+    pub ordering: MoveOrdering,
+    pub pruning: Pruning,
+    pub tt: &'a mut TranspositionTable,
 }
 
-pub fn update_game_state(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], opponent: Bitboard, last_opponent_move: &mut Option<(usize, usize)>, castle: &mut [bool; Color::COUNT], turn: bool, piece_index: usize, from_index: usize, to_index: usize) {
-    let invert_input = invert_index(to_index);
-    let mut en_passant: bool = false;
-    let piece = Piece::usize_to_piece(piece_index);
-
-    bitboards[piece_index + if !turn {0} else {Piece::COUNT}].move_piece(from_index, to_index);
-
-    if let Some((_, to)) = *last_opponent_move {
-        if to_index == to + 8 && piece == Pawn {
-            bitboards[Piece::Pawn as usize + if turn {0} else {Piece::COUNT}].remove_piece(invert_input + 8);
-            en_passant = true;
-        }
+impl<'a> SearchContext<'a> {
+    pub fn new(history: &[PositionKey], tt: &'a mut TranspositionTable) -> SearchContext<'a> {
+        SearchContext {history: history.to_vec(), nodes: 0, stop: Arc::new(AtomicBool::new(false)), deadline: None, score: 0, root_move: None,
+            line: Vec::new(), pv: Vec::new(), ordering: MoveOrdering::new(), pruning: Pruning::default(), tt}
     }
-                            
-    if piece == King && castle[turn as usize] && to_index == 6 {
-        bitboards[Piece::Rook as usize + if !turn {0} else {Piece::COUNT}].move_piece(7, 5)
+
+    // Principal variation in long coordinates, like e2e4 e7e5 g1f3
+    pub fn pv_string(&self) -> String {
+        self.pv.iter().map(Move::to_string).collect::<Vec<String>>().join(" ")
     }
-    if piece == King && castle[turn as usize] && to_index == 2 {
-        bitboards[Piece::Rook as usize + if !turn {0} else {Piece::COUNT}].move_piece(0, 3)
+
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
     }
 
-    if bitboards[piece_index + if !turn {0} else {Piece::COUNT}].bits & opponent.bits != 0 {                                        
-        if !en_passant {
-            for j in 0..Piece::COUNT {
-                if bitboards[j + if turn {0} else {Piece::COUNT}].get_bit(invert_input) == 1 {
-                    bitboards[j + if turn {0} else {Piece::COUNT}].remove_piece(invert_input);
-                    break;
-                }
-            }
+    // Whether the search must end now, the clock is only read every 1024 nodes
+    fn check_stop(&mut self) -> bool {
+        if self.nodes.is_multiple_of(1024) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.stop.store(true, Ordering::Relaxed);
         }
+        self.stopped()
     }
+}
 
-    if piece == Pawn {
-        *last_opponent_move = Some((invert_index(from_index), invert_input));
-        if to_index > 55 {
-            bitboards[Pawn  as usize + if !turn {0} else {Piece::COUNT}].remove_piece(to_index);
-            bitboards[Queen as usize + if !turn {0} else {Piece::COUNT}].add_piece(to_index);
+// Let the engine play and return its move, `history` holds the positions since the last capture or pawn move, the current one last
+// The engine thinks for about `budget`, deepening its search as long as time allows
+pub fn play_ai(position: &mut Position, history: &[PositionKey], tt: &mut TranspositionTable, budget: Duration) -> Option<Move> {
+    let mut context = SearchContext::new(history, tt);
+    context.deadline = Instant::now().checked_add(budget);
+    let best_move = think(position, &mut context, MAX_DEPTH, |_, _| {});
+
+    if let Some(m) = best_move {
+        if !position.turn {
+            println!("{}Red{} plays {}", RED, RESET, to_san(position, m));
+        } else {
+            println!("{}Blue{} plays {}", BLUE, RESET, to_san(position, m));
         }
-    } else {
-        *last_opponent_move = None;
+        position.make_move(m);
     }
+    best_move
 }
 
 
-pub fn play_player(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], turn: bool, last_opponent_move: &mut Option<(usize, usize)>, castle: &mut [bool; Color::COUNT]) {
-    let mut wrong = true;
-    let (player, opponent) = get_player_and_opponent_bitboards(&bitboards, turn);
-    let opponent = opponent.mirror();
-
-    while wrong {
-        let input = get_player_piece_input(turn);
-        if let Some(index) = algebraic_to_index(&input, turn) {
-            if player.get_bit(index) == 0 {
-                println!("No pieces here!");
-                continue;
-            }
-            
-            if let Some(piece_index) = get_piece_index_to_move(&bitboards, turn, index) {
-                let piece = Piece::usize_to_piece(piece_index);
-                let moves = player.moves(index, opponent, piece, last_opponent_move, castle[turn as usize]);
-                let move_input = get_player_move_input(turn, &moves);
-
-                if let Some(move_index) = algebraic_to_index(&move_input, turn) {
-
-                    if moves.contains(&move_index) {
-                        update_game_state(bitboards, opponent, last_opponent_move, castle, turn, piece_index, index, move_index);
-                    }
-                    else {
-                        print!("Invalid move!\n");
-                        continue;
-                    }
-                }
-                else {
-                    print!("Invalid move!\n");
-                    continue;
-                }
+// Search one ply deeper at a time until `max_depth`, the deadline or a stop, calling `report` after every finished depth.
+// The best move of the last finished depth is returned, or the one of the unfinished depth if none finished.
+// No depth is started past half the time to the deadline, as it would hardly ever finish.
+// The context brings the history, the stop flag, the deadline and the pruning switches.
+pub fn think(position: &mut Position, context: &mut SearchContext, max_depth: usize, mut report: impl FnMut(usize, &SearchContext)) -> Option<Move> {
+    context.tt.new_search();
+    let deadline = context.deadline;
 
+    let start = Instant::now();
+    let mut best_move = None;
+    for depth in 1..=max_depth.min(MAX_DEPTH) {
+        context.root_move = best_move;
+
+        // Aspiration window: the score should not move far from the one of the last depth, search just around it
+        // and widen the window on the side the score falls out of until it falls inside
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = if depth > 1 && !is_mate_score(context.score) {
+            (context.score - delta, context.score + delta)
+        } else {
+            (-INFINITY, INFINITY)
+        };
+        let found = loop {
+            let found = search_root(position, context, depth, alpha, beta);
+            if context.stopped() || found.is_none() {
+                break found;
             }
-            else{
-                print!("Invalid case!\n");
-                continue;
+            if context.score <= alpha {
+                alpha = (context.score - delta).max(-INFINITY);
+            } else if context.score >= beta {
+                beta = (context.score + delta).min(INFINITY);
+            } else {
+                break found;
             }
-            
-        } else {
-            print!("Invalid case!\n");
-            continue;
-        }  
+            delta *= 2;
+        };
 
-        wrong = !wrong;
-    }
+        if context.stopped() {
+            best_move = best_move.or(found);
+            break;
+        }
+        best_move = found;
+
+        if found.is_none() {
+            break;
+        }
+        report(depth, context);
 
-    //Check if king or rook has mooved
-    for i in 0..Color::COUNT{
-        if bitboards[(Piece::King as usize) + i*Piece::COUNT].get_bit(4) == 0 {castle[i] = false;}
-        if bitboards[(Piece::Rook as usize) + i*Piece::COUNT].get_bit(0) == 0 {castle[i] = false;}
-        if bitboards[(Piece::Rook as usize) + i*Piece::COUNT].get_bit(7) == 0 {castle[i] = false;}
+        // A forced mate will not get any better
+        if is_mate_score(context.score) {
+            break;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= start + deadline.saturating_duration_since(start) / 2) {
+            break;
+        }
     }
+
+    // Out of time before a single move was searched: any legal move is better than none
+    best_move.or_else(|| position.legal_moves().first().copied())
 }
 
-pub fn play_ai(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], turn: bool, last_opponent_move: &mut Option<(usize, usize)>, castle: &mut [bool; Color::COUNT], depth: usize, use_alpha_beta: bool) {
-    if use_alpha_beta {
-        play_alpha_beta(bitboards, turn, last_opponent_move, castle, depth);
-    } else {
-        play_minimax(bitboards, turn, last_opponent_move, castle, depth);
-    }
+pub fn is_mate_score(score: isize) -> bool {
+    score.abs() > MATE_SCORE - MAX_DEPTH as isize - 2
 }
 
+// Mate scores count plies from the root, the table keeps them counted from the position
+// so that they stay right wherever the position is met again
+fn score_to_tt(score: isize, cur_depth: isize) -> isize {
+    if is_mate_score(score) {score + score.signum() * cur_depth} else {score}
+}
 
-fn play_alpha_beta(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], turn: bool, last_opponent_move: &mut Option<(usize, usize)>, castle: &mut [bool; Color::COUNT], depth: usize) {
-    let mut best_move = None;
-    let mut alpha = std::isize::MIN;
-    let mut beta = std::isize::MAX;
+fn score_from_tt(score: isize, cur_depth: isize) -> isize {
+    if is_mate_score(score) {score - score.signum() * cur_depth} else {score}
+}
 
-    let (player, opponent) = get_player_and_opponent_bitboards(&bitboards, turn);
-    let opponent = opponent.mirror();
-    
-    let mut moves_with_scores: Vec<(usize, usize, usize, isize)> = Vec::new();
+// Time to spend on a move with `time_left` on the clock, `increment` added after each move
+// and `moves_to_go` moves before the next time control, if there is one
+pub fn time_budget(time_left: Duration, increment: Duration, moves_to_go: Option<u32>) -> Duration {
+    let moves_left = moves_to_go.unwrap_or(30).max(1);
+    // Keep a margin so the move arrives before the flag falls
+    let budget = (time_left / moves_left).saturating_add(increment / 2).min(time_left.saturating_sub(Duration::from_millis(50)));
+    budget.max(Duration::from_millis(1))
+}
 
-    for i in ORDER {
-        let piece = Piece::usize_to_piece(i);
-        let player_piece_indices = bitboards[piece as usize + if !turn { 0 } else { Piece::COUNT }].get_indices();
-        for &index in &player_piece_indices {
-            let moves = player.moves(index, opponent, piece, last_opponent_move, castle[turn as usize]);
-            for &move_index in &moves {
-                let mut cloned_bitboards = bitboards.clone();
-                let mut cloned_last_opponent_move = last_opponent_move.clone();
-                let mut cloned_castle = castle.clone();
-                update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, i, index, move_index);
-
-                let mut win = false;
-                check_win_conditions(&mut cloned_bitboards, &mut win, false);
-
-                let score;
-                if win {score = King.value() + 1000;}
-                else   {score = evaluate_board(&cloned_bitboards, !turn, cloned_last_opponent_move, cloned_castle);}
-                
-                let mut inserted = false;
-                for j in 0..moves_with_scores.len() {
-                    if score > moves_with_scores[j].3 {
-                        moves_with_scores.insert(j, (i, index, move_index, score));
-                        inserted = true;
-                        break;
-                    }
-                }
-                if !inserted {
-                    moves_with_scores.push((i, index, move_index, score));
-                }
-            }
-        }
-    }
+// Seconds as the command line and XBoard give them, maybe with a fraction, None unless finite, not negative and not too long for a Duration
+pub fn parse_seconds(text: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(text.parse().ok()?).ok()
+}
 
-    for (piece_index, from_index, to_index, _) in moves_with_scores {
-        let mut cloned_bitboards = bitboards.clone();
-        let mut cloned_last_opponent_move = last_opponent_move.clone();
-        let mut cloned_castle = castle.clone();
-        update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, piece_index, from_index, to_index);
+// Best move of the side to move found by an alpha-beta search `depth` plies deep.
+// If the search is stopped it is the best of the moves searched to the end, None if there is none.
+pub fn search_alpha_beta(position: &mut Position, context: &mut SearchContext, depth: usize) -> Option<Move> {
+    search_root(position, context, depth, -INFINITY, INFINITY)
+}
 
-        let score = alpha_beta(&mut cloned_bitboards, cloned_last_opponent_move, &mut cloned_castle, false, !turn, depth - 1, 2, alpha, beta);
-        if score > alpha {
-            alpha = score;
-            best_move = Some((piece_index, from_index, to_index));
+// Search the root within the window from `alpha` to `beta`, leaving the score of the best move and its principal variation in `context`
+fn search_root(position: &mut Position, context: &mut SearchContext, depth: usize, mut alpha: isize, beta: isize) -> Option<Move> {
+    let alpha_start = alpha;
+    let key = position.key();
+
+    // The best move of the previous iteration first, else the one the table remembers
+    let mut moves = position.legal_moves();
+    let hash_move = context.root_move.or_else(|| context.tt.probe(key).and_then(|entry| entry.best_move));
+    context.ordering.order(&mut moves, hash_move, 0, None, position.turn);
+
+    let mut best_move = None;
+    let mut best_score = -INFINITY;
+    for (i, m) in moves.into_iter().enumerate() {
+        let undo = position.make_move(m);
+        context.history.push(position.key());
+        context.line.push(Some(m));
+        let mut child_pv = Vec::new();
+        let score = if i == 0 {
+            -alpha_beta(position, context, depth - 1, 2, -beta, -alpha, &mut child_pv)
+        } else {
+            search_later_move(position, context, depth - 1, 0, 2, alpha, beta, &mut child_pv)
+        };
+        context.line.pop();
+        context.history.pop();
+        position.unmake_move(m, undo);
+
+        // The score of a move whose search was cut short means nothing
+        if context.stopped() {
+            break;
+        }
+
+        if score > best_score {
+            best_score = score;
+            best_move = Some(m);
+            context.score = score;
+            context.pv = std::iter::once(m).chain(child_pv).collect();
         }
-        if beta <= alpha {
+        alpha = alpha.max(score);
+        if alpha >= beta {
             break;
         }
     }
 
-    if let Some((piece_index, from_index, to_index)) = best_move {
-        update_game_state(bitboards, opponent, last_opponent_move, castle, turn, piece_index, from_index, to_index);
+    if !context.stopped() && best_move.is_some() {
+        context.tt.store(key, depth, score_to_tt(best_score, 1), bound(best_score, alpha_start, beta), best_move);
     }
+    best_move
 }
 
-
-fn alpha_beta(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], last_opponent_move: Option<(usize, usize)>, castle: &mut [bool; Color::COUNT], maximizing_player: bool, turn: bool, depth: usize, cur_depth: isize, mut alpha: isize, mut beta: isize) -> isize {
-    if depth == 0 {
-        let score = evaluate_board(&bitboards, !(maximizing_player^turn), last_opponent_move, *castle);
-        //print!("{score}\n");
-        //display_board(bitboards);
-
-        return score;
+// What a score found searching between `alpha` and `beta` says about the true score
+fn bound(score: isize, alpha: isize, beta: isize) -> Bound {
+    if score >= beta {
+        Bound::Lower
+    } else if score <= alpha {
+        Bound::Upper
+    } else {
+        Bound::Exact
     }
+}
 
+// Principal variation search of the position reached by a move after the first of its node: it is searched
+// only with a null window to prove it is no better, and again with the full window if it turns out to be.
+// A late move is first searched `reduction` plies shallower, and at full depth only if it beats alpha.
+#[allow(clippy::too_many_arguments)]
+fn search_later_move(position: &mut Position, context: &mut SearchContext, depth: usize, reduction: usize, cur_depth: isize, alpha: isize, beta: isize, pv: &mut Vec<Move>) -> isize {
+    let mut score = -alpha_beta(position, context, depth.saturating_sub(reduction), cur_depth, -alpha - 1, -alpha, pv);
+    if reduction > 0 && score > alpha && !context.stopped() {
+        score = -alpha_beta(position, context, depth, cur_depth, -alpha - 1, -alpha, pv);
+    }
+    if score > alpha && score < beta && !context.stopped() {
+        score = -alpha_beta(position, context, depth, cur_depth, -beta, -alpha, pv);
+    }
+    score
+}
 
-    let mut moves_with_scores: Vec<(usize, usize, usize, isize)> = Vec::new();
-    let (player, opponent) = get_player_and_opponent_bitboards(&bitboards, turn);
-    let opponent = opponent.mirror();
+// Negamax alpha-beta search: the score is for the side to move, and `pv` receives the best line found when the score is within the window
+fn alpha_beta(position: &mut Position, context: &mut SearchContext, depth: usize, cur_depth: isize, mut alpha: isize, beta: isize, pv: &mut Vec<Move>) -> isize {
+    pv.clear();
+    context.nodes += 1;
+    if context.check_stop() {
+        return 0;
+    }
 
-    for i in ORDER {
-        let piece = Piece::usize_to_piece(i);
-        let player_piece_indices = bitboards[piece as usize + if !turn { 0 } else { Piece::COUNT }].get_indices();
-        for &index in &player_piece_indices {
-            let moves = player.moves(index, opponent, piece, &last_opponent_move, castle[maximizing_player as usize]);
-            for &move_index in &moves {
-                let mut cloned_bitboards = bitboards.clone();
-                let mut cloned_last_opponent_move = last_opponent_move.clone();
-                let mut cloned_castle = castle.clone();
-                update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, i, index, move_index);
-
-                let score = evaluate_board(&cloned_bitboards, !turn, cloned_last_opponent_move, cloned_castle);
-                moves_with_scores.push((i, index, move_index, score));
-            }
-        }
+    // Going back to an earlier position is scored as a draw, no need to wait for the third time
+    if position.insufficient_material() || position.halfmove_clock >= 100 || repetitions(&context.history) > 1 {
+        return 0;
     }
 
-    if maximizing_player {
-        moves_with_scores.sort_by(|(_, _, _, score1), (_, _, _, score2)| score2.cmp(score1));
-    } else {
-        moves_with_scores.sort_by(|(_, _, _, score1), (_, _, _, score2)| score1.cmp(score2));
+    if depth == 0 {
+        return quiescence(position, context, cur_depth, alpha, beta);
     }
 
-    if maximizing_player {
-        let mut max_eval = std::isize::MIN;
-        for (piece_index, from_index, to_index, _) in moves_with_scores {
-            let mut cloned_bitboards = bitboards.clone();
-            let mut cloned_last_opponent_move = last_opponent_move.clone();
-            let mut cloned_castle = castle.clone();
-            update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, piece_index, from_index, to_index);
+    // Only searches with a null window are cut short by the table, the others must return their whole principal variation
+    let pv_node = beta - alpha > 1;
+    let key = position.key();
+    let entry = context.tt.probe(key);
+    if let Some(entry) = entry.filter(|entry| entry.depth as usize >= depth && !pv_node) {
+        let score = score_from_tt(entry.score as isize, cur_depth);
+        match entry.bound {
+            Bound::Exact                    => return score,
+            Bound::Lower if score >= beta   => return score,
+            Bound::Upper if score <= alpha  => return score,
+            _                               => {}
+        }
+    }
 
-            let mut win = false;
-            check_win_conditions(&mut cloned_bitboards, &mut win, false);
+    let in_check = position.is_in_check();
+    let pruning = context.pruning;
 
-            if win {return King.value() + 1000/cur_depth;}
+    // The selective search trusts the static score, but not in check nor where the exact score is wanted
+    let static_eval = if pv_node || in_check {None} else {Some(evaluate_board(position, position.turn))};
+    if let Some(eval) = static_eval {
+        // Reverse futility: so far above beta that the few plies left will not bring the score under it
+        if pruning.reverse_futility && depth <= REVERSE_FUTILITY_DEPTH && !is_mate_score(beta)
+            && eval - REVERSE_FUTILITY_MARGIN * depth as isize >= beta {
+            return eval;
+        }
 
-            let eval = alpha_beta(&mut cloned_bitboards, cloned_last_opponent_move, &mut cloned_castle, false, !turn, depth - 1, cur_depth+1, alpha, beta);
-            max_eval = max_eval.max(eval);
-            alpha = alpha.max(max_eval);
-            if beta <= alpha {
-                return max_eval;
+        // Razoring: so far under alpha that only captures could help, the quiescence search tells whether they do
+        if pruning.razoring && depth <= RAZORING_DEPTH && !is_mate_score(alpha)
+            && eval + RAZORING_MARGIN * depth as isize <= alpha {
+            let score = quiescence(position, context, cur_depth, alpha, beta);
+            if score <= alpha {
+                return score;
             }
         }
-        return max_eval;
-    } else {
-        let mut min_eval = std::isize::MAX;
-        for (piece_index, from_index, to_index, _) in moves_with_scores {
-            let mut cloned_bitboards = bitboards.clone();
-            let mut cloned_last_opponent_move = last_opponent_move.clone();
-            let mut cloned_castle = castle.clone();
-            update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, piece_index, from_index, to_index);
-
-            let mut win = false;
-            check_win_conditions(&mut cloned_bitboards, &mut win, false);
-
-            if win {return -(King.value() + 1000/cur_depth);}
-
-            let eval = alpha_beta(&mut cloned_bitboards, cloned_last_opponent_move, &mut cloned_castle, true, !turn, depth - 1, cur_depth+1, alpha, beta);
-            min_eval = min_eval.min(eval);
-            beta = beta.min(min_eval);
-            if beta <= alpha {
-                return min_eval;
+
+        // Null move: if passing the turn still leaves the score above beta after a shallower search, a real move will too.
+        // Never twice in a row, nor with only pawns left, where having to move can be what loses (zugzwang).
+        if pruning.null_move && depth >= NULL_MOVE_DEPTH && eval >= beta && !is_mate_score(beta)
+            && context.line.last() != Some(&None) && position.has_non_pawn_material(position.turn) {
+            let reduction = if depth >= 7 {3} else {2};
+            let undo = position.make_null_move();
+            // Repetitions do not reach across a null move
+            let history = std::mem::replace(&mut context.history, vec![position.key()]);
+            context.line.push(None);
+            let mut null_pv = Vec::new();
+            let score = -alpha_beta(position, context, depth.saturating_sub(1 + reduction), cur_depth+1, -beta, -beta + 1, &mut null_pv);
+            context.line.pop();
+            context.history = history;
+            position.unmake_null_move(undo);
+
+            if context.stopped() {
+                return 0;
+            }
+            if score >= beta {
+                return if is_mate_score(score) {beta} else {score};
             }
         }
-        return min_eval;
     }
-}
 
+    let mut moves = position.legal_moves();
+    if moves.is_empty() {
+        return terminal_score(position, cur_depth);
+    }
+
+    let ply = cur_depth as usize - 1;
+    let previous = context.line.last().copied().flatten();
+    context.ordering.order(&mut moves, entry.and_then(|entry| entry.best_move), ply, previous, position.turn);
+
+    // Futility: so near the leaves and so far under alpha that a quiet move cannot bring the score back
+    let futile = pruning.futility && depth <= FUTILITY_DEPTH && !is_mate_score(alpha)
+        && static_eval.is_some_and(|eval| eval + FUTILITY_MARGIN * depth as isize <= alpha);
 
-pub fn play_minimax(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], turn: bool, last_opponent_move: &mut Option<(usize, usize)>, castle: &mut [bool; Color::COUNT], depth: usize) {
-    let mut best_score = {std::isize::MIN};
+    let alpha_start = alpha;
+    let mut best_score = -INFINITY;
     let mut best_move = None;
 
-    let (player, opponent) = get_player_and_opponent_bitboards(&bitboards, turn);
-    let opponent = opponent.mirror();
+    for (i, m) in moves.into_iter().enumerate() {
+        let quiet = !m.is_capture() && m.promotion().is_none();
+        let undo = position.make_move(m);
+        let gives_check = position.is_in_check();
+        if futile && i > 0 && quiet && !gives_check {
+            position.unmake_move(m, undo);
+            continue;
+        }
 
-    for i in 0..Piece::COUNT {
-        let piece = Piece::usize_to_piece(i);
-        let player_piece_indices = bitboards[piece as usize + if !turn{0} else {Piece::COUNT}].get_indices();
-        for &index in &player_piece_indices {
-            let moves = player.moves(index, opponent, piece, last_opponent_move, castle[turn as usize]);
-            for &move_index in &moves {
-                let mut cloned_bitboards = bitboards.clone();
-                let mut cloned_last_opponent_move = last_opponent_move.clone();
-                let mut cloned_castle = castle.clone();
-                update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, i, index, move_index);
-
-                let score = minimax(&mut cloned_bitboards, cloned_last_opponent_move, cloned_castle, false, !turn, depth - 1);
-
-                if score > best_score {
-                    best_score = score;
-                    best_move = Some((i, index, move_index));
-                }
+        // Late move reductions: quiet moves this far down the ordering are seldom the best, they are searched shallower first
+        let reduction = if pruning.late_move_reductions && depth >= LATE_MOVE_DEPTH && i >= LATE_MOVE && quiet && !in_check && !gives_check {
+            if depth >= 6 && i >= 2 * LATE_MOVE {2} else {1}
+        } else {
+            0
+        };
+
+        context.history.push(position.key());
+        context.line.push(Some(m));
+        let mut child_pv = Vec::new();
+        let score = if i == 0 {
+            -alpha_beta(position, context, depth - 1, cur_depth+1, -beta, -alpha, &mut child_pv)
+        } else {
+            search_later_move(position, context, depth - 1, reduction, cur_depth+1, alpha, beta, &mut child_pv)
+        };
+        context.line.pop();
+        context.history.pop();
+        position.unmake_move(m, undo);
+
+        if context.stopped() {
+            return 0;
+        }
 
-            }
+        if score > best_score {
+            best_score = score;
+            best_move = Some(m);
+        }
+        if score > alpha {
+            alpha = score;
+            pv.clear();
+            pv.push(m);
+            pv.extend(child_pv);
+        }
+        if alpha >= beta {
+            context.ordering.cutoff(m, ply, previous, position.turn, depth);
+            break;
         }
     }
 
-    if let Some((piece_index, from_index, to_index)) = best_move {
-        update_game_state(bitboards, opponent, last_opponent_move, castle, turn, piece_index, from_index, to_index);
-    }
+    context.tt.store(key, depth, score_to_tt(best_score, cur_depth), bound(best_score, alpha_start, beta), best_move);
+    best_score
 }
 
-fn minimax(bitboards: &mut [Bitboard; Piece::COUNT*Color::COUNT], last_opponent_move: Option<(usize, usize)>, castle: [bool; Color::COUNT], maximizing_player: bool, turn: bool, depth: usize) -> isize {
-    if depth == 0 {
-        let eval = evaluate_board(&bitboards, !(maximizing_player^turn), last_opponent_move, castle);
-        return eval;
+// Search captures and promotions, or every evasion when in check, until the position is quiet,
+// so that a leaf is never scored in the middle of an exchange. The score is for the side to move.
+fn quiescence(position: &mut Position, context: &mut SearchContext, cur_depth: isize, mut alpha: isize, beta: isize) -> isize {
+    context.nodes += 1;
+    if context.check_stop() {
+        return 0;
+    }
+
+    let moves = position.legal_moves();
+    if moves.is_empty() {
+        return terminal_score(position, cur_depth);
     }
 
-    let (player, opponent) = get_player_and_opponent_bitboards(&bitboards, turn);
-    let opponent = opponent.mirror();
+    // Stand pat: out of check the side to move does not have to capture and can keep the static score
+    let in_check = position.is_in_check();
+    let stand_pat = evaluate_board(position, position.turn);
+    let mut best_score = if in_check {
+        -INFINITY
+    } else {
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        stand_pat
+    };
 
-    if maximizing_player {
-        let mut max_eval = std::isize::MIN;
-        for i in 0..Piece::COUNT {
-            let piece = Piece::usize_to_piece(i);
-            let player_piece_indices = bitboards[piece as usize + if !turn{0} else {Piece::COUNT}].get_indices();
-            for &index in &player_piece_indices {
-                let moves = player.moves(index, opponent, piece, &last_opponent_move, castle[maximizing_player as usize]);
-                for &move_index in &moves {
-                    let mut cloned_bitboards = bitboards.clone();
-                    let mut cloned_last_opponent_move = last_opponent_move.clone();
-                    let mut cloned_castle = castle.clone();
-                    update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, i, index, move_index);
+    let mut moves: Vec<Move> = moves.into_iter().filter(|m| in_check || m.is_capture() || m.promotion().is_some()).collect();
+    context.ordering.order(&mut moves, None, 0, None, position.turn);
 
+    for m in moves {
+        // Delta pruning: even winning the piece for nothing would leave the score below the window
+        if !in_check && m.promotion().is_none() && stand_pat + m.captured().map_or(0, |piece| piece.value()) + DELTA_MARGIN <= alpha {
+            continue;
+        }
 
+        let undo = position.make_move(m);
+        let score = -quiescence(position, context, cur_depth+1, -beta, -alpha);
+        position.unmake_move(m, undo);
 
-                    let eval = minimax(&mut cloned_bitboards, cloned_last_opponent_move, cloned_castle, !maximizing_player, !turn, depth - 1);
-                    max_eval = max_eval.max(eval);
-                }
-            }
+        if context.stopped() {
+            return 0;
         }
-        return max_eval;
-    } else {
-        let mut min_eval = std::isize::MAX;
-        for i in 0..Piece::COUNT {
-            let piece = Piece::usize_to_piece(i);
-            let player_piece_indices = bitboards[piece as usize + if !turn{0} else {Piece::COUNT}].get_indices();
-            for &index in &player_piece_indices {
-                let moves = player.moves(index, opponent, piece, &last_opponent_move, castle[maximizing_player as usize]);
-                for &move_index in &moves {
-                    let mut cloned_bitboards = bitboards.clone();
-                    let mut cloned_last_opponent_move = last_opponent_move.clone();
-                    let mut cloned_castle = castle.clone();
-                    update_game_state(&mut cloned_bitboards, opponent, &mut cloned_last_opponent_move, &mut cloned_castle, turn, i, index, move_index);
-
-                    let eval = minimax(&mut cloned_bitboards, cloned_last_opponent_move, cloned_castle, !maximizing_player, !turn, depth - 1);
-                    min_eval = min_eval.min(eval);
-                }
-            }
+
+        best_score = best_score.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
         }
-        return min_eval;
     }
+    best_score
 }
 
-fn evaluate_board(bitboards: &[Bitboard; Piece::COUNT*Color::COUNT], maximizing_player: bool, last_opponent_move: Option<(usize, usize)>, castle: [bool; Color::COUNT]) -> isize {
-
-    let (player, opponent) = get_player_and_opponent_bitboards(&bitboards, maximizing_player);
-    let opponent = opponent.mirror();
+// Score, for the side to move, of a node where it has no legal move: mated or stalemate
+fn terminal_score(position: &Position, cur_depth: isize) -> isize {
+    if !position.is_in_check() {
+        return 0;
+    }
+    -(MATE_SCORE - cur_depth)
+}
 
-    let mut white_score = 0;
-    let mut black_score = 0;
+// Score of the position from the point of view of `maximizing_player`
+fn evaluate_board(position: &Position, maximizing_player: bool) -> isize {
+    let mut player_score = 0;
+    let mut opponent_score = 0;
 
     for i in 0..Piece::COUNT {
         let piece = Piece::usize_to_piece(i);
-        white_score += bitboards[i].count_bits() as isize * piece.value();
-        black_score += bitboards[i + Piece::COUNT].count_bits() as isize * piece.value();
+        player_score   += position.pieces(piece, maximizing_player).count_bits() as isize * piece.value();
+        opponent_score += position.pieces(piece, !maximizing_player).count_bits() as isize * piece.value();
     }
 
-    for i in ORDER {
-        let piece = Piece::usize_to_piece(i);
-        let player_piece_indices = bitboards[piece as usize + if !maximizing_player { 0 } else { Piece::COUNT }].get_indices();
-        for &index in &player_piece_indices {
-            let moves = player.moves(index, opponent, piece, &last_opponent_move, castle[maximizing_player as usize]);
-            white_score += moves.len() as isize * piece.value()/10;
-        }
-
-        let opponent_piece_indices = bitboards[piece as usize + if maximizing_player { 0 } else { Piece::COUNT }].get_indices();
-        for &index in &opponent_piece_indices {
-            let moves = opponent.moves(index, opponent, piece, &last_opponent_move, castle[!maximizing_player as usize]);
-            black_score += moves.len() as isize * piece.value()/10;
+    // Mobility: legal moves of each side weighted by the moving piece value.
+    // The side not to move has no en passant target, it would only get one from our next move
+    let side_to_move = |turn: bool| {
+        let mut side = *position;
+        if side.turn != turn {
+            side.turn = turn;
+            side.en_passant = None;
+            side.hash = side.compute_hash();
         }
+        side
+    };
+    for m in side_to_move(maximizing_player).legal_moves() {
+        player_score += m.piece().value()/10;
+    }
+    for m in side_to_move(!maximizing_player).legal_moves() {
+        opponent_score += m.piece().value()/10;
     }
 
-    let material_score = if maximizing_player { black_score - white_score } else { white_score - black_score };
+    let material_score = player_score - opponent_score;
 
     let pawn_structure_score = 0;//bitboards.iter().map(|&bb| bb.evaluate_pawn_structure()).sum::<isize>();
 
-    let total_score = material_score + pawn_structure_score;
-
-    if maximizing_player {
-        total_score
-    } else {
-        -total_score
-    }
+    material_score + pawn_structure_score
 }
 
-
-pub fn display_board(bitboards: &[Bitboard]) {
+pub fn display_board(position: &Position) {
+    let bitboards = &position.bitboards;
     println!("    A  B  C  D  E  F  G  H");
     println!("  +------------------------+");
 
@@ -532,7 +670,7 @@ pub fn display_board(bitboards: &[Bitboard]) {
                     print!("{} {}{} {}", if row%2 == col%2 {WHITE_BG} else {BLACK_BG}, RED, MAP[i], RESET);
                     piece_found = true;
                     break;
-                } else if bitboards[i + Piece::COUNT].mirror().get_bit(col + row * 8) == 1 {
+                } else if bitboards[i + Piece::COUNT].get_bit(col + row * 8) == 1 {
                     print!("{} {}{} {}", if row%2 == col%2 {WHITE_BG} else {BLACK_BG}, BLUE, MAP[i], RESET);
                     piece_found = true;
                     break;
@@ -540,11 +678,26 @@ pub fn display_board(bitboards: &[Bitboard]) {
             }
             if !piece_found {
                 print!("{}   {}", if row%2 == col%2 {WHITE_BG} else {BLACK_BG}, RESET);
-                
+
             }
         }
         println!("|");
     }
 
     println!("  +------------------------+");
-}
\ No newline at end of file
+    println!("{}", fen::export(position));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds_must_be_finite_and_not_negative() {
+        assert_eq!(parse_seconds("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_seconds("0"), Some(Duration::ZERO));
+        for bad in ["-1", "inf", "NaN", "1e400", "1e20", "", "abc"] {
+            assert_eq!(parse_seconds(bad), None, "{}", bad);
+        }
+    }
+}
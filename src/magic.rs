@@ -0,0 +1,189 @@
+use std::sync::OnceLock;
+
+use crate::bitboard::Bitboard;
+
+const RANK_1: u64 = 0x00000000000000FF;
+const RANK_8: u64 = 0xFF00000000000000;
+const FILE_A: u64 = 0x0101010101010101;
+const FILE_H: u64 = 0x8080808080808080;
+
+// Sliding attacks of one square: only the blockers in `mask` matter, multiplying them by `magic`
+// and keeping the top bits gives a distinct slot in `attacks` for every set of blockers
+struct Magic {
+    mask: u64,
+    magic: u64,
+    shift: u32,
+    attacks: Vec<u64>,
+}
+
+impl Magic {
+    fn attacks(&self, occupied: u64) -> u64 {
+        self.attacks[((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize]
+    }
+}
+
+struct Tables {
+    bishop: Vec<Magic>,
+    rook: Vec<Magic>,
+}
+
+static TABLES: OnceLock<Tables> = OnceLock::new();
+
+// Ray walker of Bitboard used as the reference the tables are built from
+type Rays = fn(&Bitboard, usize, Bitboard) -> Vec<usize>;
+
+fn tables() -> &'static Tables {
+    TABLES.get_or_init(|| {
+        Tables {
+            bishop: (0..64).map(|index| find_magic(index, Bitboard::bishop_rays)).collect(),
+            rook:   (0..64).map(|index| find_magic(index, Bitboard::rook_rays)).collect(),
+        }
+    })
+}
+
+// Build the tables now rather than during the first search
+pub fn init() {
+    tables();
+}
+
+// Squares a bishop on `index` attacks, the first blocker of each ray included whatever its color
+pub fn bishop_attacks(index: usize, occupied: Bitboard) -> Bitboard {
+    Bitboard{bits: tables().bishop[index].attacks(occupied.bits)}
+}
+
+pub fn rook_attacks(index: usize, occupied: Bitboard) -> Bitboard {
+    Bitboard{bits: tables().rook[index].attacks(occupied.bits)}
+}
+
+pub fn queen_attacks(index: usize, occupied: Bitboard) -> Bitboard {
+    Bitboard{bits: bishop_attacks(index, occupied).bits | rook_attacks(index, occupied).bits}
+}
+
+fn reference_attacks(rays: Rays, index: usize, occupied: u64) -> u64 {
+    rays(&Bitboard{bits:0}, index, Bitboard{bits:occupied}).iter().fold(0, |attacks, &square| attacks | 1 << square)
+}
+
+// Seeds of the random generator for each rank, the ones Stockfish found to give magics in few attempts
+const SEEDS: [u64; 8] = [728, 10316, 55013, 32803, 12281, 15100, 16645, 255];
+
+// xorshift64*, good enough to draw magic candidates from
+fn random(seed: &mut u64) -> u64 {
+    *seed ^= *seed >> 12;
+    *seed ^= *seed << 25;
+    *seed ^= *seed >> 27;
+    seed.wrapping_mul(2685821657736338717)
+}
+
+fn find_magic(index: usize, rays: Rays) -> Magic {
+    // A piece on the last square of a ray is attacked whether or not it is there, so the edges are left out
+    let edges = ((RANK_1 | RANK_8) & !(RANK_1 << (index / 8 * 8))) | ((FILE_A | FILE_H) & !(FILE_A << (index % 8)));
+    let mask = reference_attacks(rays, index, 0) & !edges;
+    let shift = 64 - mask.count_ones();
+
+    // Every subset of the mask with its attacks
+    let mut occupancies = Vec::new();
+    let mut subset: u64 = 0;
+    loop {
+        occupancies.push((subset, reference_attacks(rays, index, subset)));
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            break;
+        }
+    }
+
+    // A slot filled during an earlier attempt counts as free, which saves clearing the table on every try
+    let mut attacks = vec![0; 1 << mask.count_ones()];
+    let mut filled_in = vec![0; 1 << mask.count_ones()];
+    let mut attempt = 0;
+    let mut seed = SEEDS[index / 8];
+    loop {
+        let magic = random(&mut seed) & random(&mut seed) & random(&mut seed);
+        if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+            continue;
+        }
+
+        attempt += 1;
+        let collision = occupancies.iter().any(|&(occupied, reference)| {
+            let slot = (occupied.wrapping_mul(magic) >> shift) as usize;
+            if filled_in[slot] != attempt {
+                filled_in[slot] = attempt;
+                attacks[slot] = reference;
+            }
+            attacks[slot] != reference
+        });
+
+        if !collision {
+            return Magic {mask, magic, shift, attacks};
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Squares reached from `index` going each way one file and rank step at a time, up to and including the first piece
+    fn slide(index: usize, occupied: u64, steps: [(i32, i32); 4]) -> u64 {
+        let mut attacks = 0;
+        for (file_step, rank_step) in steps {
+            let (mut file, mut rank) = ((index % 8) as i32 + file_step, (index / 8) as i32 + rank_step);
+            while (0..8).contains(&file) && (0..8).contains(&rank) {
+                let square = 1 << (rank * 8 + file);
+                attacks |= square;
+                if occupied & square != 0 {
+                    break;
+                }
+                file += file_step;
+                rank += rank_step;
+            }
+        }
+        attacks
+    }
+
+    const DIAGONALS: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+    const LINES: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+    #[test]
+    fn corners_on_an_empty_board() {
+        let empty = Bitboard{bits: 0};
+        // Long diagonals, without the square the bishop stands on
+        assert_eq!(bishop_attacks(56, empty).bits, 0x0002040810204080);
+        assert_eq!(bishop_attacks(7, empty).bits, 0x0102040810204000);
+        assert_eq!(bishop_attacks(0, empty).bits, 0x8040201008040200);
+        assert_eq!(bishop_attacks(63, empty).bits, 0x0040201008040201);
+        // First rank and a-file, last rank and h-file
+        assert_eq!(rook_attacks(0, empty).bits, 0x01010101010101FE);
+        assert_eq!(rook_attacks(63, empty).bits, 0x7F80808080808080);
+        assert_eq!(reference_attacks(Bitboard::bishop_rays, 56, 0), 0x0002040810204080);
+        assert_eq!(reference_attacks(Bitboard::bishop_rays, 7, 0), 0x0102040810204000);
+    }
+
+    #[test]
+    fn blockers_are_attacked_and_stop_the_ray() {
+        // Rook on a1 hemmed in by b1 and a2, bishop on d4 with pieces on f6 and b2
+        assert_eq!(rook_attacks(0, Bitboard{bits: 1 << 1 | 1 << 8}).bits, 1 << 1 | 1 << 8);
+        assert_eq!(bishop_attacks(27, Bitboard{bits: 1 << 45 | 1 << 9}).bits, 1 << 36 | 1 << 45 | 1 << 18 | 1 << 9 | 1 << 34 | 1 << 41 | 1 << 48 | 1 << 20 | 1 << 13 | 1 << 6);
+    }
+
+    // Random boards, sparse and crowded, checked on every square against a walker that shares no code with the tables
+    #[test]
+    fn lookups_match_a_plain_walker() {
+        let mut seed = 0x9E3779B97F4A7C15;
+        for i in 0..300 {
+            let occupied = match i % 3 {
+                0 => random(&mut seed) & random(&mut seed) & random(&mut seed),
+                1 => random(&mut seed) & random(&mut seed),
+                _ => random(&mut seed),
+            };
+            for index in 0..64 {
+                let board = Bitboard{bits: occupied};
+                let (diagonals, lines) = (slide(index, occupied, DIAGONALS), slide(index, occupied, LINES));
+                assert_eq!(bishop_attacks(index, board).bits, diagonals, "bishop on {} with {:#x}", index, occupied);
+                assert_eq!(rook_attacks(index, board).bits, lines, "rook on {} with {:#x}", index, occupied);
+                assert_eq!(queen_attacks(index, board).bits, diagonals | lines);
+                assert_eq!(reference_attacks(Bitboard::bishop_rays, index, occupied), diagonals, "bishop rays from {} with {:#x}", index, occupied);
+                assert_eq!(reference_attacks(Bitboard::rook_rays, index, occupied), lines, "rook rays from {} with {:#x}", index, occupied);
+            }
+        }
+    }
+}
@@ -8,7 +8,7 @@ use std::io;
 use itertools::max;
 use strum::EnumCount;
 use strum_macros::{EnumCount as EnumCountMacro, EnumIter};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use itertools::Itertools;
 use std::mem::transmute;
 
@@ -17,49 +17,187 @@ mod pieces;
 mod bitboard;
 mod game;
 mod color;
+mod position;
+mod moves;
+mod bench;
+mod magic;
+mod attacks;
+mod fen;
+mod perft;
+mod zobrist;
+mod san;
+mod pgn;
+mod uci;
+mod xboard;
+mod tt;
+mod ordering;
 
 use color::*;
 use game::*;
+use position::*;
 use bitboard::Bitboard;
 use pieces::Piece;
 use Piece::*;
 use pieces::MAP;
 
 fn main() {
-    let max_turn: f64 = 300.0;
-
-    let mut bitboards: [Bitboard; Piece::COUNT * Color::COUNT] = get_bitboards();
-    let mut last_opponent_move: Option<(usize, usize)> = None;
-    let mut castle: [bool; Color::COUNT] = [true; Color::COUNT];
-    
-    let mut turn: bool = false;
-    let mut win : bool = false;
+    magic::init();
+
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // `--fen <record>` may come anywhere and sets the position to start from instead of the usual one
+    let mut position = match take_option(&mut args, "--fen") {
+        Some(record) => fen::parse(&record).unwrap_or_else(|error| {
+            eprintln!("Invalid FEN: {}", error);
+            std::process::exit(1);
+        }),
+        None => Position::new(),
+    };
+
+    // `--load <file>` resumes the last game of a PGN file, `--save <file>` is where games are recorded
+    let save_path = take_option(&mut args, "--save").unwrap_or("games.pgn".to_string());
+    let loaded = take_option(&mut args, "--load").map(|path| {
+        let loaded = std::fs::read_to_string(&path).map_err(|error| error.to_string())
+            .and_then(|pgn| pgn::parse_last(&pgn).map(|(game, start)| (game, pgn, start)).map_err(|error| error.to_string()));
+        let (game, pgn, start) = loaded.unwrap_or_else(|error| {
+            eprintln!("Cannot load {}: {}", path, error);
+            std::process::exit(1);
+        });
+        // A game resumed from the save file is written back in its place, with the games before it kept as they were
+        let same_file = std::fs::canonicalize(&path).ok() == std::fs::canonicalize(&save_path).ok();
+        (game, same_file.then(|| pgn[..start].to_string()))
+    });
+
+    // `--clock <minutes>[+<seconds>]` is the engine's time for the game and what it gains per move
+    let clock = take_option(&mut args, "--clock").unwrap_or("5+2".to_string());
+    let (mut engine_clock, increment) = parse_clock(&clock).unwrap_or_else(|| {
+        eprintln!("Invalid clock {}, expected minutes and optional increment seconds like 5+2", clock);
+        std::process::exit(1);
+    });
+
+    if args.get(1).map(String::as_str) == Some("--bench") {
+        let depth = args.get(2).and_then(|d| d.parse().ok()).unwrap_or(4);
+        bench::run(depth);
+        return;
+    }
+
+    // Move generator checks: perft and divide from the starting position, or the whole reference suite
+    match args.get(1).map(String::as_str) {
+        Some("--perft") | Some("--divide") => {
+            let depth = args.get(2).and_then(|d| d.parse().ok()).unwrap_or(4);
+            if args[1] == "--perft" {
+                perft::run_perft(&mut position, depth);
+            } else {
+                perft::run_divide(&mut position, depth);
+            }
+            return;
+        }
+        Some("--uci") => {
+            uci::run();
+            return;
+        }
+        Some("--xboard") => {
+            xboard::run();
+            return;
+        }
+        Some("--perft-suite") => {
+            if !perft::run_suite() {
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let (mut game, games_before) = match loaded {
+        Some(loaded) => loaded,
+        None         => (pgn::Game::new(position), None),
+    };
+    position = game.position();
+
     let mut count_turn: f64 = 0.0;
 
+    // Positions reached since the last capture or pawn move
+    let mut history: Vec<PositionKey> = game.history();
+    let mut game_over = pgn::result(&position, &history) != "*";
+
     let mut time_mean: f64 = 0.0;
 
-    while !win && count_turn < max_turn {
-        display_board(&bitboards);
+    // Kept from move to move, what the engine learnt searching one move still holds for the next
+    let mut tt = tt::TranspositionTable::new(tt::DEFAULT_MB);
+
+    while !game_over {
+        display_board(&position);
 
         let start = Instant::now();
-        if turn {
-            play_player(&mut bitboards, turn, &mut last_opponent_move, &mut castle);
+        let played = if position.turn {
+            match play_player(&mut position) {
+                Some(m) => Some(m),
+                None    => {
+                    println!("Input closed, the game is adjourned.");
+                    break;
+                }
+            }
         }
         else {
-            play_ai(&mut bitboards, turn, &mut last_opponent_move, &mut castle, 5, true);
-        }
+            let budget = time_budget(engine_clock, increment, None);
+            let played = play_ai(&mut position, &history, &mut tt, budget);
+            engine_clock = engine_clock.saturating_sub(start.elapsed()).saturating_add(increment);
+            played
+        };
         let duration = start.elapsed();
+        game.moves.extend(played);
 
         time_mean += duration.as_secs_f64();
 
-        check_win_conditions(&bitboards, &mut win, true);
-        turn = !turn;
+        position.add_to_history(&mut history);
+
+        game_over = announce_game_status(position.status(&history), position.turn);
+
+        if !game_over {
+            if let Some(reason) = position.claimable_draw(&history) {
+                announce_draw(reason);
+                game_over = true;
+            }
+        }
 
         count_turn += 1.0;
         
     }
 
-    display_board(&bitboards);
-    print!("{}ms", (time_mean/count_turn)*1000.0);
+    display_board(&position);
+    if count_turn > 0.0 {
+        print!("{}ms", (time_mean/count_turn)*1000.0);
+    }
+
+    game.set_tag("Result", pgn::result(&position, &history));
+    let saved = match games_before {
+        Some(before) => std::fs::write(&save_path, before + &game.to_pgn()),
+        None => std::fs::OpenOptions::new().create(true).append(true).open(&save_path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, game.to_pgn().as_bytes())),
+    };
+    match saved {
+        Ok(())     => println!("\nGame saved to {}", save_path),
+        Err(error) => eprintln!("\nCannot save the game to {}: {}", save_path, error),
+    }
+}
 
+// Minutes and increment seconds of a clock written like 5+2, or 10 without increment
+fn parse_clock(text: &str) -> Option<(Duration, Duration)> {
+    let (minutes, seconds) = text.split_once('+').unwrap_or((text, "0"));
+    let time = parse_seconds(minutes)?.checked_mul(60)?;
+    if time.is_zero() {
+        return None;
+    }
+    Some((time, parse_seconds(seconds)?))
+}
+
+// Remove `name` and the value after it from the arguments and return the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    if i + 1 >= args.len() {
+        eprintln!("{} needs a value", name);
+        std::process::exit(1);
+    }
+    args.drain(i..=i + 1).nth(1)
 }
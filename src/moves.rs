@@ -0,0 +1,105 @@
+use std::fmt;
+
+use crate::game::index_to_algebraic;
+use crate::pieces::Piece;
+
+use Piece::*;
+
+// No piece in a 3 bits piece field
+const NO_PIECE: u32 = 7;
+
+const FROM_SHIFT: u32      = 0;
+const TO_SHIFT: u32        = 6;
+const PIECE_SHIFT: u32     = 12;
+const CAPTURED_SHIFT: u32  = 15;
+const PROMOTION_SHIFT: u32 = 18;
+const FLAGS_SHIFT: u32     = 21;
+
+// A move packed in 32 bits:
+// from (6) | to (6) | moving piece (3) | captured piece (3) | promotion piece (3) | flags (4).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Move(u32);
+
+impl Move {
+    pub const CASTLE: u32      = 1;
+    pub const EN_PASSANT: u32  = 1 << 1;
+    pub const DOUBLE_PUSH: u32 = 1 << 2;
+
+    pub fn new(from: usize, to: usize, piece: Piece, captured: Option<Piece>, promotion: Option<Piece>, flags: u32) -> Move {
+        let piece_bits = |p: Option<Piece>| p.map_or(NO_PIECE, |p| p as u32);
+        Move(
+            (from as u32) << FROM_SHIFT
+            | (to as u32) << TO_SHIFT
+            | (piece as u32) << PIECE_SHIFT
+            | piece_bits(captured) << CAPTURED_SHIFT
+            | piece_bits(promotion) << PROMOTION_SHIFT
+            | flags << FLAGS_SHIFT
+        )
+    }
+
+    fn field(&self, shift: u32, width: u32) -> u32 {
+        (self.0 >> shift) & ((1 << width) - 1)
+    }
+
+    fn piece_field(&self, shift: u32) -> Option<Piece> {
+        match self.field(shift, 3) {
+            NO_PIECE => None,
+            p        => Some(Piece::usize_to_piece(p as usize)),
+        }
+    }
+
+    pub fn from(&self) -> usize {
+        self.field(FROM_SHIFT, 6) as usize
+    }
+
+    pub fn to(&self) -> usize {
+        self.field(TO_SHIFT, 6) as usize
+    }
+
+    pub fn piece(&self) -> Piece {
+        Piece::usize_to_piece(self.field(PIECE_SHIFT, 3) as usize)
+    }
+
+    pub fn captured(&self) -> Option<Piece> {
+        self.piece_field(CAPTURED_SHIFT)
+    }
+
+    pub fn promotion(&self) -> Option<Piece> {
+        self.piece_field(PROMOTION_SHIFT)
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.field(FLAGS_SHIFT, 4)
+    }
+
+    pub fn is_capture(&self) -> bool {
+        self.captured().is_some()
+    }
+
+    pub fn is_castle(&self) -> bool {
+        self.flags() & Move::CASTLE != 0
+    }
+
+    pub fn is_en_passant(&self) -> bool {
+        self.flags() & Move::EN_PASSANT != 0
+    }
+
+    pub fn is_double_push(&self) -> bool {
+        self.flags() & Move::DOUBLE_PUSH != 0
+    }
+}
+
+// Coordinate notation, like e2e4 or e7e8q
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let from = index_to_algebraic(self.from()).to_lowercase();
+        let to = index_to_algebraic(self.to()).to_lowercase();
+        match self.promotion() {
+            Some(Knight) => write!(f, "{}{}n", from, to),
+            Some(Bishop) => write!(f, "{}{}b", from, to),
+            Some(Rook)   => write!(f, "{}{}r", from, to),
+            Some(_)      => write!(f, "{}{}q", from, to),
+            None         => write!(f, "{}{}", from, to),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::cmp::Reverse;
+
+use strum::EnumCount;
+
+use crate::game::MAX_DEPTH;
+use crate::moves::Move;
+use crate::pieces::Piece;
+
+// Moves are tried kind by kind, the score within a kind only orders the moves of that kind
+const HASH_MOVE_SCORE: i32    = 4_000_000;
+const CAPTURE_SCORE: i32      = 3_000_000;
+const PROMOTION_SCORE: i32    = 2_000_000;
+const KILLER_SCORE: i32       = 1_000_000;
+const COUNTER_MOVE_SCORE: i32 = 900_000;
+
+// History scores stay below this, the whole table is halved when one reaches it
+const HISTORY_MAX: i32 = 800_000;
+
+// What the search learns about good quiet moves, to try them first where they may be good again
+pub struct MoveOrdering {
+    // Two quiet moves that caused a beta cutoff at each ply, the latest first
+    killers: [[Option<Move>; 2]; MAX_DEPTH + 1],
+    // Quiet move that refuted each move, by side to move, piece and destination of the move refuted
+    counter_moves: [[[Option<Move>; 64]; Piece::COUNT]; 2],
+    // Butterfly table: how often each quiet move, by side to move, origin and destination, caused a cutoff, deeper ones counting more
+    history: [[[i32; 64]; 64]; 2],
+}
+
+// Most valuable victim, then least valuable attacker
+pub fn mvv_lva(m: Move) -> i32 {
+    m.captured().map_or(0, |victim| victim.value() as i32 * 10 - m.piece().value() as i32)
+}
+
+fn is_quiet(m: Move) -> bool {
+    !m.is_capture() && m.promotion().is_none()
+}
+
+impl MoveOrdering {
+    pub fn new() -> MoveOrdering {
+        MoveOrdering {killers: [[None; 2]; MAX_DEPTH + 1], counter_moves: [[[None; 64]; Piece::COUNT]; 2], history: [[[0; 64]; 64]; 2]}
+    }
+
+    fn score(&self, m: Move, hash_move: Option<Move>, ply: usize, previous: Option<Move>, turn: bool) -> i32 {
+        if Some(m) == hash_move {
+            return HASH_MOVE_SCORE;
+        }
+        if m.is_capture() {
+            return CAPTURE_SCORE + mvv_lva(m) + m.promotion().map_or(0, |piece| piece.value() as i32);
+        }
+        if let Some(piece) = m.promotion() {
+            return PROMOTION_SCORE + piece.value() as i32;
+        }
+        if let Some(i) = self.killers.get(ply).and_then(|killers| killers.iter().position(|&killer| killer == Some(m))) {
+            return KILLER_SCORE - i as i32;
+        }
+        if previous.is_some_and(|p| self.counter_moves[turn as usize][p.piece() as usize][p.to()] == Some(m)) {
+            return COUNTER_MOVE_SCORE;
+        }
+        self.history[turn as usize][m.from()][m.to()]
+    }
+
+    // Sort the moves of the side to move `turn` at `ply`, reached by `previous`, the most promising first
+    pub fn order(&self, moves: &mut [Move], hash_move: Option<Move>, ply: usize, previous: Option<Move>, turn: bool) {
+        moves.sort_by_cached_key(|&m| Reverse(self.score(m, hash_move, ply, previous, turn)));
+    }
+
+    // Remember `m`, which caused a beta cutoff `depth` plies from the leaves
+    pub fn cutoff(&mut self, m: Move, ply: usize, previous: Option<Move>, turn: bool, depth: usize) {
+        if !is_quiet(m) {
+            return;
+        }
+
+        if let Some(killers) = self.killers.get_mut(ply) {
+            if killers[0] != Some(m) {
+                killers[1] = killers[0];
+                killers[0] = Some(m);
+            }
+        }
+        if let Some(p) = previous {
+            self.counter_moves[turn as usize][p.piece() as usize][p.to()] = Some(m);
+        }
+
+        let entry = &mut self.history[turn as usize][m.from()][m.to()];
+        *entry += (depth * depth) as i32;
+        if *entry >= HISTORY_MAX {
+            self.history.iter_mut().flatten().flatten().for_each(|score| *score /= 2);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::Instant;
+
+use crate::fen;
+use crate::moves::Move;
+use crate::position::Position;
+
+// Well-known positions with their published node counts from depth 1 on,
+// between them they go through castling, en passant, promotions and discovered checks
+const SUITE: [(&str, &str, &[u64]); 7] = [
+    ("start position", fen::START_FEN,
+        &[20, 400, 8902, 197281, 4865609]),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        &[48, 2039, 97862, 4085603]),
+    ("position 3", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        &[14, 191, 2812, 43238, 674624]),
+    ("position 4", "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        &[6, 264, 9467, 422333]),
+    ("position 4 mirrored", "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        &[6, 264, 9467, 422333]),
+    ("position 5", "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        &[44, 1486, 62379, 2103487]),
+    ("position 6", "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        &[46, 2079, 89890, 3894594]),
+];
+
+// Number of move sequences of length `depth` from `position`
+pub fn perft(position: &mut Position, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = position.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for m in moves {
+        let undo = position.make_move(m);
+        nodes += perft(position, depth - 1);
+        position.unmake_move(m, undo);
+    }
+    nodes
+}
+
+// Perft split by root move, to find which move a wrong count comes from
+pub fn divide(position: &mut Position, depth: usize) -> Vec<(Move, u64)> {
+    let mut split = Vec::new();
+    for m in position.legal_moves() {
+        let undo = position.make_move(m);
+        split.push((m, perft(position, depth.saturating_sub(1))));
+        position.unmake_move(m, undo);
+    }
+    split
+}
+
+pub fn run_perft(position: &mut Position, depth: usize) {
+    let start = Instant::now();
+    let nodes = perft(position, depth);
+    let seconds = start.elapsed().as_secs_f64();
+    println!("perft {} nodes {} time {:.3}s nps {:.0}", depth, nodes, seconds, nodes as f64 / seconds);
+}
+
+pub fn run_divide(position: &mut Position, depth: usize) {
+    let split = divide(position, depth);
+    for (m, nodes) in &split {
+        println!("{}: {}", m, nodes);
+    }
+    println!("moves {} nodes {}", split.len(), split.iter().map(|(_, nodes)| nodes).sum::<u64>());
+}
+
+// Check the move generator against every count of SUITE, and FEN export against its records, true if they all match
+pub fn run_suite() -> bool {
+    let mut passed = true;
+
+    for (name, fen, counts) in SUITE {
+        let mut position = fen::parse(fen).expect("invalid FEN in the perft suite");
+        if fen::export(&position) != fen {
+            println!("{} exported as {} FAILED", name, fen::export(&position));
+            passed = false;
+        }
+        for (i, &expected) in counts.iter().enumerate() {
+            let depth = i + 1;
+            let nodes = perft(&mut position, depth);
+            if nodes == expected {
+                println!("{} depth {} nodes {} ok", name, depth, nodes);
+            } else {
+                println!("{} depth {} nodes {} expected {} FAILED", name, depth, nodes, expected);
+                passed = false;
+            }
+        }
+    }
+
+    println!("{}", if passed {"perft suite passed"} else {"perft suite FAILED"});
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts up to this many nodes are checked by default, the deeper ones only with --ignored
+    const QUICK_NODES: u64 = 100_000;
+
+    fn check_suite(deep: bool) {
+        for (name, record, counts) in SUITE {
+            let mut position = fen::parse(record).expect("invalid FEN in the perft suite");
+            for (i, &expected) in counts.iter().enumerate() {
+                if (expected > QUICK_NODES) == deep {
+                    assert_eq!(perft(&mut position, i + 1), expected, "{} depth {}", name, i + 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn suite_shallow() {
+        check_suite(false);
+    }
+
+    #[test]
+    #[ignore]
+    fn suite_deep() {
+        check_suite(true);
+    }
+
+    #[test]
+    fn suite_records_export_unchanged() {
+        for (name, record, _) in SUITE {
+            let position = fen::parse(record).expect("invalid FEN in the perft suite");
+            assert_eq!(fen::export(&position), record, "{}", name);
+        }
+    }
+
+    #[test]
+    fn perft_leaves_the_position_unchanged() {
+        for (name, record, _) in SUITE {
+            let mut position = fen::parse(record).expect("invalid FEN in the perft suite");
+            let before = position;
+            perft(&mut position, 3);
+            assert_eq!(position, before, "{}", name);
+        }
+    }
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let (_, record, counts) = SUITE[1];
+        let mut position = fen::parse(record).expect("invalid FEN in the perft suite");
+        let split = divide(&mut position, 2);
+        assert_eq!(split.len() as u64, counts[0]);
+        assert_eq!(split.iter().map(|(_, nodes)| nodes).sum::<u64>(), counts[1]);
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fen::{self, FenError};
+use crate::moves::Move;
+use crate::position::*;
+use crate::san::{parse_san, to_san};
+
+// Tags every PGN game carries, in the order they are written
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+// Longest line of movetext written out
+const LINE_LENGTH: usize = 80;
+
+// Why a PGN game could not be read
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PgnError {
+    BadTag(String),
+    BadFen(FenError),
+    // Full move number and SAN of a move that is not legal in the game
+    IllegalMove(usize, String),
+    UnterminatedComment,
+    UnterminatedVariation,
+    NoGame,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::BadTag(tag)              => write!(f, "invalid tag pair [{}]", tag),
+            PgnError::BadFen(error)            => write!(f, "invalid FEN tag: {}", error),
+            PgnError::IllegalMove(number, san) => write!(f, "illegal move {} on move {}", san, number),
+            PgnError::UnterminatedComment      => write!(f, "comment without a closing brace"),
+            PgnError::UnterminatedVariation    => write!(f, "variation without a closing parenthesis"),
+            PgnError::NoGame                   => write!(f, "no game found"),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+// A game as recorded in PGN: its tags, the position it started from and the moves played since
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub start: Position,
+    pub moves: Vec<Move>,
+}
+
+impl Game {
+    // A game between the engine, playing White, and a human, starting from `start` today
+    pub fn new(start: Position) -> Game {
+        let mut game = Game {tags: Vec::new(), start, moves: Vec::new()};
+        game.set_tag("Event", "Casual game");
+        game.set_tag("Site", "?");
+        game.set_tag("Date", &today());
+        game.set_tag("Round", "-");
+        game.set_tag("White", "Engine");
+        game.set_tag("Black", "Human");
+        game.set_tag("Result", "*");
+        if start != Position::new() {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &fen::export(&start));
+        }
+        game
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None           => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    // Position reached after all the moves
+    pub fn position(&self) -> Position {
+        let mut position = self.start;
+        for &m in &self.moves {
+            position.make_move(m);
+        }
+        position
+    }
+
+    // Positions since the last capture or pawn move, the final one last, as the repetition rules need them
+    pub fn history(&self) -> Vec<PositionKey> {
+        let mut position = self.start;
+        let mut history = vec![position.key()];
+        for &m in &self.moves {
+            position.make_move(m);
+            position.add_to_history(&mut history);
+        }
+        history
+    }
+
+    // The game in PGN, the Seven Tag Roster first
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        let roster = SEVEN_TAG_ROSTER.iter().map(|&name| (name, self.tag(name).unwrap_or("?")));
+        let others = self.tags.iter().filter(|(name, _)| !SEVEN_TAG_ROSTER.contains(&name.as_str())).map(|(name, value)| (name.as_str(), value.as_str()));
+        for (name, value) in roster.chain(others) {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        let mut position = self.start;
+        for (i, &m) in self.moves.iter().enumerate() {
+            if !position.turn {
+                tokens.push(format!("{}.", position.fullmove_number));
+            } else if i == 0 {
+                tokens.push(format!("{}...", position.fullmove_number));
+            }
+            tokens.push(to_san(&position, m));
+            position.make_move(m);
+        }
+        tokens.push(self.tag("Result").unwrap_or("*").to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+// PGN result of a game that stands at `position`, "*" while it goes on
+pub fn result(position: &Position, history: &[PositionKey]) -> &'static str {
+    match position.status(history) {
+        GameStatus::Checkmate => if position.turn {"1-0"} else {"0-1"},
+        GameStatus::Stalemate | GameStatus::Draw(_) => "1/2-1/2",
+        _ if position.claimable_draw(history).is_some() => "1/2-1/2",
+        _ => "*",
+    }
+}
+
+// Read the first game of `pgn` and replay its moves.
+// Comments, NAGs and variations are skipped, only the main line is kept.
+pub fn parse(pgn: &str) -> Result<Game, PgnError> {
+    read_game(pgn).map(|(game, _)| game)
+}
+
+// Read the last game of `pgn`, a file of games one after the other, along with the offset its text starts at
+pub fn parse_last(pgn: &str) -> Result<(Game, usize), PgnError> {
+    let mut last = None;
+    let mut offset = 0;
+    loop {
+        let start = pgn.len() - pgn[offset..].trim_start().len();
+        match read_game(&pgn[start..]) {
+            Ok((game, length)) => {
+                last = Some((game, start));
+                offset = start + length;
+            }
+            // Nothing but whitespace or comments after the last game
+            Err(PgnError::NoGame) => return last.ok_or(PgnError::NoGame),
+            Err(error)            => return Err(error),
+        }
+    }
+}
+
+// Read the first game of `pgn` along with the length of its text, up to its result
+fn read_game(pgn: &str) -> Result<(Game, usize), PgnError> {
+    let mut tags = Vec::new();
+    let mut tokens = Vec::new();
+    let mut chars = pgn.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '[' if tokens.is_empty() => {
+                let pair: String = chars.by_ref().map(|(_, c)| c).take_while(|&c| c != ']').collect();
+                tags.push(parse_tag(&pair)?);
+            }
+            '{' => {
+                if !chars.by_ref().any(|(_, c)| c == '}') {
+                    return Err(PgnError::UnterminatedComment);
+                }
+            }
+            ';' => {
+                chars.by_ref().find(|&(_, c)| c == '\n');
+            }
+            '(' => skip_variation(&mut chars)?,
+            '$' => {
+                while chars.next_if(|(_, c)| c.is_ascii_digit()).is_some() {}
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some((_, c)) = chars.next_if(|&(_, c)| !c.is_whitespace() && !"{}();[$".contains(c)) {
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+        // A game ends with its result, what follows belongs to the next one
+        if tokens.last().is_some_and(|token| ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str())) {
+            break;
+        }
+    }
+
+    if tags.is_empty() && tokens.is_empty() {
+        return Err(PgnError::NoGame);
+    }
+    let length = chars.peek().map_or(pgn.len(), |&(i, _)| i);
+
+    let start = match tags.iter().find(|(name, _): &&(String, String)| name == "FEN") {
+        Some((_, record)) => fen::parse(record).map_err(PgnError::BadFen)?,
+        None              => Position::new(),
+    };
+    let mut game = Game {tags, start, moves: Vec::new()};
+    let mut position = start;
+
+    for token in tokens {
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+            game.set_tag("Result", &token);
+            break;
+        }
+        // Move numbers like 12. or 12... may be glued to the move that follows.
+        // Digits only count as one when a dot follows, castling may be written 0-0.
+        let san = match token.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) if i > 0 && token[i..].starts_with('.') => token[i..].trim_start_matches('.'),
+            Some(_) => token.as_str(),
+            None    => "",
+        };
+        if san.is_empty() {
+            continue;
+        }
+        let m = parse_san(&position, san).ok_or(PgnError::IllegalMove(position.fullmove_number, san.to_string()))?;
+        position.make_move(m);
+        game.moves.push(m);
+    }
+
+    Ok((game, length))
+}
+
+fn parse_tag(pair: &str) -> Result<(String, String), PgnError> {
+    let bad = || PgnError::BadTag(pair.to_string());
+    let (name, value) = pair.trim().split_once(char::is_whitespace).ok_or_else(bad)?;
+    let value = value.trim().strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(bad)?;
+    Ok((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+// Skip a variation, its opening parenthesis already read, with the variations and comments nested in it
+fn skip_variation(chars: &mut std::iter::Peekable<std::str::CharIndices>) -> Result<(), PgnError> {
+    let mut depth = 1;
+    while depth > 0 {
+        match chars.next().map(|(_, c)| c) {
+            Some('(') => depth += 1,
+            Some(')') => depth -= 1,
+            Some('{') => {
+                if !chars.by_ref().any(|(_, c)| c == '}') {
+                    return Err(PgnError::UnterminatedComment);
+                }
+            }
+            Some(_)   => {}
+            None      => return Err(PgnError::UnterminatedVariation),
+        }
+    }
+    Ok(())
+}
+
+// Today's date as PGN writes it, like 2024.03.09
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+
+    // Civil date of a day count since 1970-01-01, after Howard Hinnant's days_from_civil inverse
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moves(game: &Game) -> Vec<String> {
+        let mut position = game.start;
+        game.moves.iter().map(|&m| {
+            let san = to_san(&position, m);
+            position.make_move(m);
+            san
+        }).collect()
+    }
+
+    #[test]
+    fn skips_comments_nags_and_variations() {
+        let game = parse("1. e4 {best by test} e5 $1 2. Nf3 (2. f4 exf4 (2... d5 {counter} 3. exd5) 3. Nf3) ; rest of line\n2... Nc6 *").unwrap();
+        assert_eq!(moves(&game), ["e4", "e5", "Nf3", "Nc6"]);
+        assert_eq!(game.tag("Result"), Some("*"));
+    }
+
+    #[test]
+    fn reads_the_last_of_several_games() {
+        let first = "[Event \"First\"]\n\n1. e4 e5 {a 1-0 in a comment} 1/2-1/2\n\n";
+        let last = "[Event \"Last\"]\n\n1. d4 d5 2. c4 *\n";
+        let pgn = format!("{}{}\n; trailing note\n", first, last);
+        let (game, start) = parse_last(&pgn).unwrap();
+        assert_eq!(game.tag("Event"), Some("Last"));
+        assert_eq!(moves(&game), ["d4", "d5", "c4"]);
+        assert_eq!(start, first.len());
+        assert_eq!(parse_last(first).unwrap().1, 0);
+        assert_eq!(parse_last("  \n").err(), Some(PgnError::NoGame));
+    }
+
+    #[test]
+    fn reads_move_numbers_glued_to_moves() {
+        let game = parse("1.e4 1...e5 2.Nf3 Nc6 3.Bb5 a6 4.Ba4 4...Nf6 12.O-O 0-1").unwrap();
+        assert_eq!(moves(&game), ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O"]);
+        assert_eq!(game.tag("Result"), Some("0-1"));
+    }
+
+    #[test]
+    fn reads_castling_written_with_zeros() {
+        let game = parse("1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 d6 5. d4 Bg4 6. h3 Qd7 7. hxg4 0-0-0 *").unwrap();
+        assert_eq!(moves(&game)[6], "O-O");
+        assert_eq!(moves(&game)[13], "O-O-O");
+    }
+
+    #[test]
+    fn unescapes_tags() {
+        let game = parse("[Event \"The \\\"Big\\\" one\"]\n[Site \"C:\\\\games\"]\n\n1. e4 *").unwrap();
+        assert_eq!(game.tag("Event"), Some("The \"Big\" one"));
+        assert_eq!(game.tag("Site"), Some("C:\\games"));
+        assert!(game.to_pgn().contains("[Event \"The \\\"Big\\\" one\"]"));
+    }
+
+    #[test]
+    fn reports_malformed_games() {
+        assert_eq!(parse("1. e4 {unclosed").unwrap_err(), PgnError::UnterminatedComment);
+        assert_eq!(parse("1. e4 (1. d4 d5").unwrap_err(), PgnError::UnterminatedVariation);
+        assert_eq!(parse("[Event Casual]").unwrap_err(), PgnError::BadTag("Event Casual".to_string()));
+        assert_eq!(parse("  \n").unwrap_err(), PgnError::NoGame);
+        assert_eq!(parse("1. e4 e5 2. Ke3 *").unwrap_err(), PgnError::IllegalMove(2, "Ke3".to_string()));
+    }
+
+    #[test]
+    fn written_games_read_back_the_same() {
+        let mut game = parse("[FEN \"r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1\"]\n1... O-O-O 2. O-O Rhe8 *").unwrap();
+        game.set_tag("Result", "*");
+        let again = parse(&game.to_pgn()).unwrap();
+        assert_eq!(again.start, game.start);
+        assert_eq!(again.moves, game.moves);
+    }
+}
@@ -1,5 +1,3 @@
-use std::usize;
-
 use strum::EnumCount;
 use strum_macros::{EnumCount as EnumCountMacro, EnumIter};
 
@@ -38,6 +36,24 @@ impl Piece {
         }
     }
 
+    // Piece named by a letter of MAP, knights can also be written 'N'
+    pub fn from_char(c: char) -> Option<Piece> {
+        match c.to_ascii_uppercase() {
+            'P'       => Some(Piece::Pawn),
+            'N' | 'H' => Some(Piece::Knight),
+            'B'       => Some(Piece::Bishop),
+            'R'       => Some(Piece::Rook),
+            'Q'       => Some(Piece::Queen),
+            'K'       => Some(Piece::King),
+            _         => None
+        }
+    }
+
+    // Letter of the piece in SAN and FEN, where knights are N rather than the H of MAP
+    pub fn letter(&self) -> char {
+        if *self == Piece::Knight {'N'} else {MAP[*self as usize]}
+    }
+
     pub fn value(&self) -> isize{
         VAL[*self as usize]
     }
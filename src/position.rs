@@ -0,0 +1,495 @@
+use crate::bitboard::Bitboard;
+use crate::pieces::Piece;
+use crate::game::Color;
+use crate::moves::Move;
+use crate::magic::{bishop_attacks, rook_attacks};
+use crate::attacks::{knight_attacks, king_attacks, pawn_attacks};
+use crate::zobrist;
+
+use Piece::*;
+
+use strum::EnumCount;
+
+const ORDER: [usize; Piece::COUNT] = [Pawn as usize, Knight as usize, Bishop as usize, Rook as usize,  Queen as usize, King as usize];
+
+// Pieces a pawn can turn into on the last rank, the most likely first
+pub const PROMOTIONS: [Piece; 4] = [Queen, Knight, Rook, Bishop];
+
+pub const KINGSIDE: usize = 0;
+pub const QUEENSIDE: usize = 1;
+
+// Castling rights of each color, indexed by KINGSIDE and QUEENSIDE
+pub type CastleRights = [[bool; 2]; Color::COUNT];
+
+// Zobrist hash of everything that makes two positions the same for the repetition rules
+pub type PositionKey = u64;
+
+// Squares of the same color as A1
+const DARK_SQUARES: u64 = 0xAA55AA55AA55AA55;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DrawReason {
+    FiftyMoveRule,
+    SeventyFiveMoveRule,
+    ThreefoldRepetition,
+    FivefoldRepetition,
+    InsufficientMaterial,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum GameStatus {
+    Ongoing,
+    Check,
+    Checkmate,
+    Stalemate,
+    Draw(DrawReason),
+}
+
+// What make_move loses for good and unmake_move needs back, the captured piece travels in the Move itself
+#[derive(Debug, Copy, Clone)]
+pub struct Undo {
+    pub castle: CastleRights,
+    pub en_passant: Option<usize>,
+    pub halfmove_clock: usize,
+    pub hash: u64,
+}
+
+// The whole state of a game at one point in time.
+// Squares are the same for both colors, from A1 = 0 to H8 = 63, rank by rank.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub bitboards: [Bitboard; Piece::COUNT*Color::COUNT],
+    pub turn: bool,
+    pub castle: CastleRights,
+    pub en_passant: Option<usize>,
+    pub halfmove_clock: usize,
+    pub fullmove_number: usize,
+    // Zobrist hash, kept up to date by make_move
+    pub hash: u64,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Position {
+    pub fn new() -> Position {
+        let mut bitboards: [Bitboard; Piece::COUNT*Color::COUNT] = [Bitboard{bits:0}; Piece::COUNT*Color::COUNT];
+
+        bitboards[Pawn as usize].bits                  = 0b0000000000000000000000000000000000000000000000001111111100000000;
+        bitboards[(Pawn as usize)+Piece::COUNT].bits   = 0b0000000011111111000000000000000000000000000000000000000000000000;
+
+        bitboards[Rook as usize].bits                  = 0b0000000000000000000000000000000000000000000000000000000010000001;
+        bitboards[(Rook as usize)+Piece::COUNT].bits   = 0b1000000100000000000000000000000000000000000000000000000000000000;
+
+        bitboards[Knight as usize].bits                = 0b0000000000000000000000000000000000000000000000000000000001000010;
+        bitboards[(Knight as usize)+Piece::COUNT].bits = 0b0100001000000000000000000000000000000000000000000000000000000000;
+
+        bitboards[Bishop as usize].bits                = 0b0000000000000000000000000000000000000000000000000000000000100100;
+        bitboards[(Bishop as usize)+Piece::COUNT].bits = 0b0010010000000000000000000000000000000000000000000000000000000000;
+
+        bitboards[Queen as usize].bits                 = 0b0000000000000000000000000000000000000000000000000000000000001000;
+        bitboards[(Queen as usize)+Piece::COUNT].bits  = 0b0000100000000000000000000000000000000000000000000000000000000000;
+
+        bitboards[King as usize].bits                  = 0b0000000000000000000000000000000000000000000000000000000000010000;
+        bitboards[(King as usize)+Piece::COUNT].bits   = 0b0001000000000000000000000000000000000000000000000000000000000000;
+
+        let mut position = Position {
+            bitboards,
+            turn: false,
+            castle: [[true; 2]; Color::COUNT],
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            hash: 0,
+        };
+        position.hash = position.compute_hash();
+        position
+    }
+
+    // Bitboard of `piece` for the side `turn`
+    pub fn pieces(&self, piece: Piece, turn: bool) -> Bitboard {
+        self.bitboards[piece as usize + if !turn {0} else {Piece::COUNT}]
+    }
+
+    // All the pieces of `turn` and all the pieces of its opponent
+    pub fn get_player_and_opponent_bitboards(&self, turn: bool) -> (Bitboard, Bitboard) {
+        let mut player = Bitboard{bits:0};
+        let mut opponent = Bitboard{bits:0};
+
+        for i in 0..Piece::COUNT {
+            player.combine_bitboard(self.bitboards[i + if !turn {0} else {Piece::COUNT}]);
+            opponent.combine_bitboard(self.bitboards[i + if turn {0} else {Piece::COUNT}]);
+        }
+        (player, opponent)
+    }
+
+    // Piece of `turn` standing on `index`
+    pub fn piece_at(&self, turn: bool, index: usize) -> Option<Piece> {
+        (0..Piece::COUNT).find(|&i| self.bitboards[i + if !turn {0} else {Piece::COUNT}].get_bit(index) == 1).map(Piece::usize_to_piece)
+    }
+
+    // Tell if the square `index` is attacked by the opponent of `turn`
+    pub fn is_square_attacked(&self, turn: bool, index: usize) -> bool {
+        let (player, opponent) = self.get_player_and_opponent_bitboards(turn);
+        let enemy = |piece: Piece| self.pieces(piece, !turn);
+
+        let queens = enemy(Queen);
+        let diagonal = Bitboard{bits: enemy(Bishop).bits | queens.bits};
+        let straight = Bitboard{bits: enemy(Rook).bits | queens.bits};
+
+        let occupied = Bitboard{bits: player.bits | opponent.bits};
+
+        // Opponent pawns attack `index` from the squares a pawn of `turn` standing there would attack
+        pawn_attacks(index, turn).bits & enemy(Pawn).bits != 0
+            || knight_attacks(index).bits & enemy(Knight).bits != 0
+            || king_attacks(index).bits & enemy(King).bits != 0
+            || bishop_attacks(index, occupied).bits & diagonal.bits != 0
+            || rook_attacks(index, occupied).bits & straight.bits != 0
+    }
+
+    pub fn is_king_attacked(&self, turn: bool) -> bool {
+        match self.pieces(King, turn).get_indices().first() {
+            Some(&king) => self.is_square_attacked(turn, king),
+            None        => false,
+        }
+    }
+
+    pub fn is_in_check(&self) -> bool {
+        self.is_king_attacked(self.turn)
+    }
+
+    // Castling destinations of the king to move: the rights are still there, the rook is home,
+    // the squares in between are empty and the king neither starts, crosses nor ends on an attacked square
+    pub fn castle_moves(&self) -> Bitboard {
+        let mut moves = Bitboard{bits:0};
+        let turn = self.turn;
+        let (player, opponent) = self.get_player_and_opponent_bitboards(turn);
+        let occupied = player.bits | opponent.bits;
+        let home = home_rank(turn);
+
+        let castle_right: u64 = 0b0000000000000000000000000000000000000000000000000000000001100000 << home;
+        let castle_left : u64 = 0b0000000000000000000000000000000000000000000000000000000000001110 << home;
+
+        if self.pieces(King, turn).get_bit(home + 4) == 0 || self.is_square_attacked(turn, home + 4) {
+            return moves;
+        }
+
+        if self.castle[turn as usize][KINGSIDE] && self.pieces(Rook, turn).get_bit(home + 7) == 1 && occupied & castle_right == 0
+            && !self.is_square_attacked(turn, home + 5) && !self.is_square_attacked(turn, home + 6) {
+            moves.add_piece(home + 6);
+        }
+
+        if self.castle[turn as usize][QUEENSIDE] && self.pieces(Rook, turn).get_bit(home) == 1 && occupied & castle_left == 0
+            && !self.is_square_attacked(turn, home + 3) && !self.is_square_attacked(turn, home + 2) {
+            moves.add_piece(home + 2);
+        }
+
+        moves
+    }
+
+    // The move of `piece` of the side to move between two squares, with what it captures and its flags
+    pub fn new_move(&self, piece: Piece, from_index: usize, to_index: usize, promotion: Option<Piece>) -> Move {
+        let mut flags = 0;
+        let mut captured = self.piece_at(!self.turn, to_index);
+        let home = home_rank(self.turn);
+
+        if piece == King && from_index == home + 4 && (to_index == home + 6 || to_index == home + 2) {
+            flags |= Move::CASTLE;
+        }
+        if piece == Pawn && self.en_passant == Some(to_index) {
+            flags |= Move::EN_PASSANT;
+            captured = Some(Pawn);
+        }
+        if piece == Pawn && from_index.abs_diff(to_index) == 16 {
+            flags |= Move::DOUBLE_PUSH;
+        }
+
+        Move::new(from_index, to_index, piece, captured, promotion, flags)
+    }
+
+    // Every move of the side to move that does not leave its own king in check
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut legal = Vec::new();
+        let mut scratch = *self;
+        let turn = self.turn;
+        let (player, opponent) = self.get_player_and_opponent_bitboards(turn);
+        let last_rank = if !turn {7} else {0};
+
+        for i in ORDER {
+            let piece = Piece::usize_to_piece(i);
+            let player_piece_indices = self.pieces(piece, turn).get_indices();
+            for &index in &player_piece_indices {
+                let mut moves = player.moves(index, opponent, piece, &self.en_passant, turn);
+                if piece == King {
+                    moves.combine_bitboard(self.castle_moves());
+                }
+                for move_index in moves.get_indices() {
+                    let m = self.new_move(piece, index, move_index, None);
+                    let undo = scratch.make_move(m);
+                    let leaves_king_attacked = scratch.is_king_attacked(turn);
+                    scratch.unmake_move(m, undo);
+
+                    if leaves_king_attacked {
+                        continue;
+                    }
+
+                    if piece == Pawn && move_index / 8 == last_rank {
+                        for promotion in PROMOTIONS {
+                            legal.push(self.new_move(piece, index, move_index, Some(promotion)));
+                        }
+                    } else {
+                        legal.push(m);
+                    }
+                }
+            }
+        }
+
+        debug_assert_eq!(scratch, *self, "unmake_move did not restore the position");
+        legal
+    }
+
+    // Play a move of the side to move and hand the turn over
+    pub fn make_move(&mut self, m: Move) -> Undo {
+        let undo = Undo {castle: self.castle, en_passant: self.en_passant, halfmove_clock: self.halfmove_clock, hash: self.hash};
+        let turn = self.turn;
+        let from_index = m.from();
+        let to_index = m.to();
+        let piece = m.piece();
+        let home = home_rank(turn);
+
+        // Castling rights and en passant are hashed out now and back in once the move is played
+        self.hash ^= zobrist::castle(&self.castle) ^ self.en_passant_hash();
+
+        self.toggle_piece(piece, turn, from_index);
+        self.toggle_piece(piece, turn, to_index);
+
+        // The pawn taken en passant stands right behind the target square
+        if m.is_en_passant() {
+            self.toggle_piece(Pawn, !turn, en_passant_victim(turn, to_index));
+        } else if let Some(captured) = m.captured() {
+            self.toggle_piece(captured, !turn, to_index);
+        }
+
+        if m.is_castle() {
+            let (rook_from, rook_to) = if to_index == home + 6 {(home + 7, home + 5)} else {(home, home + 3)};
+            self.toggle_piece(Rook, turn, rook_from);
+            self.toggle_piece(Rook, turn, rook_to);
+        }
+
+        // Moving the king or a rook loses the rights, so does having a rook taken on its square
+        if piece == King {
+            self.castle[turn as usize] = [false, false];
+        }
+        let opponent_home = home_rank(!turn);
+        if from_index == home + 7 {self.castle[turn as usize][KINGSIDE]  = false;}
+        if from_index == home     {self.castle[turn as usize][QUEENSIDE] = false;}
+        if to_index == opponent_home + 7 {self.castle[!turn as usize][KINGSIDE]  = false;}
+        if to_index == opponent_home     {self.castle[!turn as usize][QUEENSIDE] = false;}
+
+        // After a double push the square jumped over is the opponent's en passant target
+        self.en_passant = if m.is_double_push() {Some((from_index + to_index) / 2)} else {None};
+
+        if let Some(promotion) = m.promotion() {
+            self.toggle_piece(Pawn, turn, to_index);
+            self.toggle_piece(promotion, turn, to_index);
+        }
+
+        // A capture or a pawn move means no earlier position can come back
+        if m.is_capture() || piece == Pawn {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if turn {
+            self.fullmove_number += 1;
+        }
+        self.turn = !turn;
+        self.hash ^= zobrist::side() ^ zobrist::castle(&self.castle) ^ self.en_passant_hash();
+
+        debug_assert_eq!(self.hash, self.compute_hash(), "incremental hash differs after {}", m);
+        undo
+    }
+
+    // Take back `m`, the last move played, with the record make_move returned for it
+    pub fn unmake_move(&mut self, m: Move, undo: Undo) {
+        let turn = !self.turn;
+        let from_index = m.from();
+        let to_index = m.to();
+        let piece = m.piece();
+        let home = home_rank(turn);
+
+        if let Some(promotion) = m.promotion() {
+            self.bitboards[promotion as usize + if !turn {0} else {Piece::COUNT}].remove_piece(to_index);
+            self.bitboards[Pawn as usize + if !turn {0} else {Piece::COUNT}].add_piece(to_index);
+        }
+
+        self.bitboards[piece as usize + if !turn {0} else {Piece::COUNT}].move_piece(to_index, from_index);
+
+        if m.is_en_passant() {
+            self.bitboards[Pawn as usize + if turn {0} else {Piece::COUNT}].add_piece(en_passant_victim(turn, to_index));
+        } else if let Some(captured) = m.captured() {
+            self.bitboards[captured as usize + if turn {0} else {Piece::COUNT}].add_piece(to_index);
+        }
+
+        if m.is_castle() && to_index == home + 6 {
+            self.bitboards[Rook as usize + if !turn {0} else {Piece::COUNT}].move_piece(home + 5, home + 7)
+        }
+        if m.is_castle() && to_index == home + 2 {
+            self.bitboards[Rook as usize + if !turn {0} else {Piece::COUNT}].move_piece(home + 3, home)
+        }
+
+        self.castle = undo.castle;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.hash = undo.hash;
+        if turn {
+            self.fullmove_number -= 1;
+        }
+        self.turn = turn;
+    }
+
+    // Hand the turn over without moving, as null-move pruning asks. Only the side to move and the en passant target change.
+    pub fn make_null_move(&mut self) -> Undo {
+        let undo = Undo {castle: self.castle, en_passant: self.en_passant, halfmove_clock: self.halfmove_clock, hash: self.hash};
+
+        self.hash ^= self.en_passant_hash() ^ zobrist::side();
+        self.en_passant = None;
+        self.halfmove_clock += 1;
+        if self.turn {
+            self.fullmove_number += 1;
+        }
+        self.turn = !self.turn;
+
+        debug_assert_eq!(self.hash, self.compute_hash(), "incremental hash differs after a null move");
+        undo
+    }
+
+    // Take back the null move that returned `undo`
+    pub fn unmake_null_move(&mut self, undo: Undo) {
+        self.turn = !self.turn;
+        if self.turn {
+            self.fullmove_number -= 1;
+        }
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.hash = undo.hash;
+    }
+
+    // Whether `turn` has a piece besides its king and pawns, without one zugzwang is common
+    pub fn has_non_pawn_material(&self, turn: bool) -> bool {
+        [Knight, Bishop, Rook, Queen].iter().any(|&piece| self.pieces(piece, turn).count_bits() > 0)
+    }
+
+    // Nobody can ever deliver mate: bare kings, a single minor piece, or only bishops all on the same color
+    pub fn insufficient_material(&self) -> bool {
+        let count = |piece: Piece| self.pieces(piece, false).count_bits() + self.pieces(piece, true).count_bits();
+
+        if count(Pawn) + count(Rook) + count(Queen) > 0 {
+            return false;
+        }
+
+        let knights = count(Knight);
+        let bishops = count(Bishop);
+        if knights + bishops <= 1 {
+            return true;
+        }
+
+        let all_bishops = self.pieces(Bishop, false).bits | self.pieces(Bishop, true).bits;
+        knights == 0 && (all_bishops & DARK_SQUARES == 0 || all_bishops & !DARK_SQUARES == 0)
+    }
+
+    pub fn key(&self) -> PositionKey {
+        self.hash
+    }
+
+    // Add the position, just reached, to the positions since the last capture or pawn move that the repetition rules look at.
+    // A capture or pawn move starts the list over, as nothing before it can come back.
+    pub fn add_to_history(&self, history: &mut Vec<PositionKey>) {
+        if self.halfmove_clock == 0 {
+            history.clear();
+        }
+        history.push(self.key());
+    }
+
+    // Put `piece` of `turn` on `index` if the square is empty, take it away otherwise, and update the hash
+    fn toggle_piece(&mut self, piece: Piece, turn: bool, index: usize) {
+        let bitboard = piece as usize + if !turn {0} else {Piece::COUNT};
+        self.bitboards[bitboard].bits ^= 1 << index;
+        self.hash ^= zobrist::piece(bitboard, index);
+    }
+
+    // The en passant square only makes a difference when a pawn is there to take,
+    // otherwise it is left out of the hash
+    fn en_passant_hash(&self) -> u64 {
+        match self.en_passant {
+            Some(target) if pawn_attacks(target, !self.turn).bits & self.pieces(Pawn, self.turn).bits != 0 => zobrist::en_passant(target % 8),
+            _ => 0,
+        }
+    }
+
+    // Hash of the position computed from scratch
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (bitboard, pieces) in self.bitboards.iter().enumerate() {
+            for index in pieces.get_indices() {
+                hash ^= zobrist::piece(bitboard, index);
+            }
+        }
+        if self.turn {
+            hash ^= zobrist::side();
+        }
+        hash ^ zobrist::castle(&self.castle) ^ self.en_passant_hash()
+    }
+
+    // Status of the game for the side to move,
+    // `history` holds the positions since the last capture or pawn move, the current one last
+    pub fn status(&self, history: &[PositionKey]) -> GameStatus {
+        let in_check = self.is_in_check();
+
+        if self.legal_moves().is_empty() {
+            if in_check {GameStatus::Checkmate} else {GameStatus::Stalemate}
+        } else if self.insufficient_material() {
+            GameStatus::Draw(DrawReason::InsufficientMaterial)
+        } else if self.halfmove_clock >= 150 {
+            GameStatus::Draw(DrawReason::SeventyFiveMoveRule)
+        } else if repetitions(history) >= 5 {
+            GameStatus::Draw(DrawReason::FivefoldRepetition)
+        } else if in_check {
+            GameStatus::Check
+        } else {
+            GameStatus::Ongoing
+        }
+    }
+
+    // Draws a player may claim but that do not end the game by themselves
+    pub fn claimable_draw(&self, history: &[PositionKey]) -> Option<DrawReason> {
+        if self.halfmove_clock >= 100 {
+            Some(DrawReason::FiftyMoveRule)
+        } else if repetitions(history) >= 3 {
+            Some(DrawReason::ThreefoldRepetition)
+        } else {
+            None
+        }
+    }
+}
+
+// First square of the rank the pieces of `turn` start on
+fn home_rank(turn: bool) -> usize {
+    if !turn {0} else {56}
+}
+
+// Square of the pawn taken by a pawn of `turn` landing on the en passant target `to_index`
+fn en_passant_victim(turn: bool, to_index: usize) -> usize {
+    if !turn {to_index - 8} else {to_index + 8}
+}
+
+// Number of times the last position of `history` has been reached
+pub fn repetitions(history: &[PositionKey]) -> usize {
+    match history.last() {
+        Some(current) => history.iter().filter(|&key| key == current).count(),
+        None          => 0,
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+
+use crate::game::index_to_algebraic;
+use crate::moves::Move;
+use crate::pieces::Piece;
+use crate::position::Position;
+
+use Piece::*;
+
+// Standard Algebraic Notation of `m`, a legal move of the side to move in `position`, like Nbd7, exd6 or e8=Q+
+pub fn to_san(position: &Position, m: Move) -> String {
+    let mut san = san_without_check(position, m);
+
+    let mut after = *position;
+    after.make_move(m);
+    if after.is_in_check() {
+        san.push(if after.legal_moves().is_empty() {'#'} else {'+'});
+    }
+    san
+}
+
+fn san_without_check(position: &Position, m: Move) -> String {
+    if m.is_castle() {
+        return if m.to() % 8 == 6 {"O-O".to_string()} else {"O-O-O".to_string()};
+    }
+
+    let square = |index: usize| index_to_algebraic(index).to_lowercase();
+    let mut san = String::new();
+
+    if m.piece() == Pawn {
+        if m.is_capture() {
+            san.push_str(&square(m.from())[..1]);
+        }
+    } else {
+        san.push(m.piece().letter());
+
+        // Name the file of the piece if that is enough to tell it from the others going to the same square, else its rank, else both
+        let others: Vec<Move> = position.legal_moves().into_iter()
+            .filter(|o| o.piece() == m.piece() && o.to() == m.to() && o.from() != m.from())
+            .collect();
+        if !others.is_empty() {
+            let from = square(m.from());
+            if others.iter().all(|o| o.from() % 8 != m.from() % 8) {
+                san.push_str(&from[..1]);
+            } else if others.iter().all(|o| o.from() / 8 != m.from() / 8) {
+                san.push_str(&from[1..]);
+            } else {
+                san.push_str(&from);
+            }
+        }
+    }
+
+    if m.is_capture() {
+        san.push('x');
+    }
+    san.push_str(&square(m.to()));
+
+    if let Some(promotion) = m.promotion() {
+        san.push('=');
+        san.push(promotion.letter());
+    }
+    san
+}
+
+// Check marks, annotations like ! or ?, a trailing e.p. and the = of promotions may be left out, castling may use zeros
+fn normalize(san: &str) -> String {
+    san.trim()
+        .trim_end_matches(['+', '#', '!', '?'])
+        .trim_end_matches("e.p.")
+        .trim_end()
+        .trim_end_matches(['+', '#'])
+        .replace('0', "O")
+        .replace('=', "")
+}
+
+// What a SAN move tells: castling, or the piece, whatever is given of the square it leaves, where it goes and its promotion
+enum Written {
+    Castle {kingside: bool},
+    Move {piece: Piece, from_file: Option<usize>, from_rank: Option<usize>, to: usize, promotion: Option<Piece>},
+}
+
+// Read `text` as SAN, in any case. A leading B is a bishop and b a file; the other piece letters are pieces in either case.
+fn read(text: &str) -> Option<Written> {
+    let text = normalize(text);
+    match text.to_uppercase().as_str() {
+        "O-O"   => return Some(Written::Castle {kingside: true}),
+        "O-O-O" => return Some(Written::Castle {kingside: false}),
+        _       => {}
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+    let piece = match chars.first()? {
+        &c if "KQRBN".contains(c) || "kqrn".contains(c) => {
+            chars.remove(0);
+            Piece::from_char(c)?
+        }
+        _ => Pawn,
+    };
+    let mut chars: Vec<char> = chars.into_iter().map(|c| c.to_ascii_lowercase()).collect();
+
+    let promotion = match chars.last()? {
+        &c if "qrbn".contains(c) => {
+            chars.pop();
+            Piece::from_char(c)
+        }
+        _ => None,
+    };
+
+    let file = |c: char| ('a'..='h').contains(&c).then(|| c as usize - 'a' as usize);
+    let rank = |c: char| ('1'..='8').contains(&c).then(|| c as usize - '1' as usize);
+    let to_rank = rank(chars.pop()?)?;
+    let to_file = file(chars.pop()?)?;
+    if chars.last() == Some(&'x') {
+        chars.pop();
+    }
+
+    // What is left tells the square the piece leaves: its file, its rank, both or nothing
+    let mut from = chars.into_iter().peekable();
+    let from_file = from.next_if(|&c| file(c).is_some()).and_then(file);
+    let from_rank = from.next_if(|&c| rank(c).is_some()).and_then(rank);
+    if from.next().is_some() {
+        return None;
+    }
+
+    Some(Written::Move {piece, from_file, from_rank, to: to_rank * 8 + to_file, promotion})
+}
+
+impl Written {
+    fn names(&self, m: Move) -> bool {
+        match *self {
+            Written::Castle {kingside} => m.is_castle() && (m.to() % 8 == 6) == kingside,
+            Written::Move {piece, from_file, from_rank, to, promotion} => {
+                !m.is_castle() && m.piece() == piece && m.to() == to && m.promotion() == promotion
+                    && from_file.is_none_or(|file| m.from() % 8 == file)
+                    && from_rank.is_none_or(|rank| m.from() / 8 == rank)
+                    // A pawn that changes file captures, and SAN then names the file it comes from
+                    && (piece != Pawn || from_file.is_some() || m.from() % 8 == to % 8)
+            }
+        }
+    }
+}
+
+// Every legal move `text` could mean when read as SAN
+fn written_moves(position: &Position, text: &str) -> Vec<Move> {
+    let Some(written) = read(text) else {return Vec::new()};
+    position.legal_moves().into_iter().filter(|&m| written.names(m)).collect()
+}
+
+// The legal move of the side to move written `text` in SAN, None if there is none or more than one
+pub fn parse_san(position: &Position, text: &str) -> Option<Move> {
+    match written_moves(position, text)[..] {
+        [m] => Some(m),
+        _   => None,
+    }
+}
+
+// Why what a player typed is not a move they can play
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseMoveError {
+    Illegal(String),
+    // The text and the SAN of every move it could mean
+    Ambiguous(String, Vec<String>),
+}
+
+impl fmt::Display for ParseMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseMoveError::Illegal(text)          => write!(f, "{} is not a legal move", text),
+            ParseMoveError::Ambiguous(text, moves) => write!(f, "{} could be {}", text, moves.join(" or ")),
+        }
+    }
+}
+
+// The legal move of the side to move typed as `text` by a player, in SAN (Nf3, exd5, O-O, e8=Q+)
+// or long coordinates (e2e4, e7e8q), in any case. A square the piece leaves may be given even when
+// it is not needed (Rad1, Nc3d5). Bxc3 is always a bishop and bxc3 a pawn, whatever else could take.
+pub fn parse_move(position: &Position, text: &str) -> Result<Move, ParseMoveError> {
+    let text = text.trim();
+
+    let coordinates = text.to_lowercase().replace('=', "");
+    if let Some(m) = position.legal_moves().into_iter().find(|m| m.to_string() == coordinates) {
+        return Ok(m);
+    }
+
+    let matches = written_moves(position, text);
+    match matches.len() {
+        0 => Err(ParseMoveError::Illegal(text.to_string())),
+        1 => Ok(matches[0]),
+        _ => Err(ParseMoveError::Ambiguous(text.to_string(), matches.iter().map(|&m| to_san(position, m)).collect())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    // SAN of the move `text` stands for in the position `fen`
+    fn typed(fen: &str, text: &str) -> Result<String, ParseMoveError> {
+        let position = fen::parse(fen).unwrap();
+        parse_move(&position, text).map(|m| to_san(&position, m))
+    }
+
+    #[test]
+    fn piece_letters_are_never_read_as_files() {
+        // Only the pawn on b2 can take on c3
+        let fen = "4k3/8/8/8/8/2p5/1PB5/4K3 w - - 0 1";
+        assert_eq!(typed(fen, "bxc3"), Ok("bxc3".to_string()));
+        assert_eq!(typed(fen, "Bxc3"), Err(ParseMoveError::Illegal("Bxc3".to_string())));
+        assert_eq!(typed(fen, "BXC3"), Err(ParseMoveError::Illegal("BXC3".to_string())));
+
+        // Both can
+        let fen = "4k3/8/8/8/8/2p5/1P1B4/4K3 w - - 0 1";
+        assert_eq!(typed(fen, "bxc3"), Ok("bxc3".to_string()));
+        assert_eq!(typed(fen, "Bxc3"), Ok("Bxc3".to_string()));
+        assert_eq!(typed(fen, "BXC3"), Ok("Bxc3".to_string()));
+    }
+
+    #[test]
+    fn reads_any_case() {
+        assert_eq!(typed(fen::START_FEN, "nf3"), Ok("Nf3".to_string()));
+        assert_eq!(typed(fen::START_FEN, "NF3"), Ok("Nf3".to_string()));
+        assert_eq!(typed(fen::START_FEN, "E4"), Ok("e4".to_string()));
+        assert_eq!(typed(fen::START_FEN, "g1f3"), Ok("Nf3".to_string()));
+    }
+
+    #[test]
+    fn accepts_unneeded_disambiguation() {
+        // The king stands between the h1 rook and d1
+        let fen = "4k3/8/8/8/8/2N5/8/R3K2R w - - 0 1";
+        assert_eq!(typed(fen, "Rad1"), Ok("Rd1".to_string()));
+        assert_eq!(typed(fen, "R1d1"), Ok("Rd1".to_string()));
+        assert_eq!(typed(fen, "Ra1d1"), Ok("Rd1".to_string()));
+        assert_eq!(typed(fen, "Nc3d5"), Ok("Nd5".to_string()));
+        assert_eq!(typed(fen, "Rhd1"), Err(ParseMoveError::Illegal("Rhd1".to_string())));
+    }
+
+    #[test]
+    fn reports_ambiguous_moves() {
+        let fen = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert_eq!(typed(fen, "Nd2"), Err(ParseMoveError::Ambiguous("Nd2".to_string(), vec!["Nbd2".to_string(), "Nfd2".to_string()])));
+        assert_eq!(typed(fen, "Nbd2"), Ok("Nbd2".to_string()));
+        assert_eq!(typed(fen, "N1d2"), Err(ParseMoveError::Ambiguous("N1d2".to_string(), vec!["Nbd2".to_string(), "Nfd2".to_string()])));
+        assert_eq!(parse_san(&fen::parse(fen).unwrap(), "Nd2"), None);
+    }
+
+    #[test]
+    fn reads_castling_with_zeros() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(typed(fen, "O-O"), Ok("O-O".to_string()));
+        assert_eq!(typed(fen, "0-0"), Ok("O-O".to_string()));
+        assert_eq!(typed(fen, "0-0-0"), Ok("O-O-O".to_string()));
+        assert_eq!(typed(fen, "o-o-o"), Ok("O-O-O".to_string()));
+        assert_eq!(typed(fen, "Kg1"), Err(ParseMoveError::Illegal("Kg1".to_string())));
+    }
+
+    #[test]
+    fn reads_en_passant_and_promotions() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2";
+        assert_eq!(typed(fen, "exd6 e.p."), Ok("exd6".to_string()));
+        assert_eq!(typed(fen, "exd6e.p."), Ok("exd6".to_string()));
+        assert_eq!(typed(fen, "e5d6"), Ok("exd6".to_string()));
+
+        let fen = "4k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(typed(fen, "b8=Q"), Ok("b8=Q+".to_string()));
+        assert_eq!(typed(fen, "b8Q"), Ok("b8=Q+".to_string()));
+        assert_eq!(typed(fen, "b8n"), Ok("b8=N".to_string()));
+        assert_eq!(typed(fen, "b7b8r"), Ok("b8=R+".to_string()));
+        assert_eq!(typed(fen, "b8"), Err(ParseMoveError::Illegal("b8".to_string())));
+    }
+}
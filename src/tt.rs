@@ -0,0 +1,93 @@
+use std::mem::size_of;
+
+use crate::moves::Move;
+use crate::position::PositionKey;
+
+// Size of the table when nothing else is asked, in megabytes
+pub const DEFAULT_MB: usize = 16;
+
+// Largest table either protocol may ask for, in megabytes
+pub const MAX_MB: usize = 4096;
+
+// What a stored score says about the true score of the position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // The search failed high: the true score is at least the stored one
+    Lower,
+    // The search failed low: the true score is at most the stored one
+    Upper,
+}
+
+// What a search learnt about a position, scores are for the side to move
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    key: PositionKey,
+    pub depth: u8,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+    // Search that wrote the entry, entries of older searches are the first replaced
+    age: u8,
+}
+
+// Fixed-size hash table of searched positions, indexed by their Zobrist key
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    age: u8,
+}
+
+impl TranspositionTable {
+    pub fn new(mb: usize) -> TranspositionTable {
+        let count = (mb * 1024 * 1024 / size_of::<Option<Entry>>()).max(1);
+        TranspositionTable {entries: vec![None; count], age: 0}
+    }
+
+    // Give the table a new size, forgetting everything in it
+    pub fn resize(&mut self, mb: usize) {
+        *self = TranspositionTable::new(mb);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+        self.age = 0;
+    }
+
+    // To call before each search, so the entries of the previous ones age
+    pub fn new_search(&mut self) {
+        self.age = self.age.wrapping_add(1);
+    }
+
+    fn index(&self, key: PositionKey) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn probe(&self, key: PositionKey) -> Option<Entry> {
+        self.entries[self.index(key)].filter(|entry| entry.key == key)
+    }
+
+    // Store the result of a search `depth` plies deep. The entry in the slot is replaced
+    // if it is about the same position, was written by an earlier search or searched no deeper.
+    pub fn store(&mut self, key: PositionKey, depth: usize, score: isize, bound: Bound, best_move: Option<Move>) {
+        let index = self.index(key);
+        let age = self.age;
+        let slot = &mut self.entries[index];
+
+        if let Some(old) = slot {
+            if old.key != key && old.age == age && old.depth as usize > depth {
+                return;
+            }
+        }
+
+        // A search that found no best move, like a fail low, keeps the one known from before
+        let best_move = best_move.or(slot.filter(|old| old.key == key).and_then(|old| old.best_move));
+        *slot = Some(Entry {key, depth: depth as u8, score: score as i32, bound, best_move, age});
+    }
+
+    // Permille of the table used by the current search, estimated on its first thousand slots
+    pub fn hashfull(&self) -> usize {
+        let sample = &self.entries[..self.entries.len().min(1000)];
+        let used = sample.iter().filter(|slot| slot.is_some_and(|entry| entry.age == self.age)).count();
+        used * 1000 / sample.len()
+    }
+}
@@ -0,0 +1,211 @@
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::fen;
+use crate::game::{think, time_budget, is_mate_score, Pruning, SearchContext, MATE_SCORE, MAX_DEPTH};
+use crate::position::*;
+use crate::tt::{TranspositionTable, DEFAULT_MB, MAX_MB};
+
+pub const NAME: &str = "chess";
+const AUTHOR: &str = "xerneas02";
+
+// What a go command asks for
+#[derive(Debug, Default, Clone, Copy)]
+struct Limits {
+    depth: Option<usize>,
+    movetime: Option<u64>,
+    // Milliseconds left on the clocks and added per move, White first
+    time: [Option<u64>; 2],
+    increment: [u64; 2],
+    movestogo: Option<u32>,
+    infinite: bool,
+}
+
+// The game as the GUI last set it up
+struct State {
+    position: Position,
+    // Positions since the last capture or pawn move, the current one last
+    history: Vec<PositionKey>,
+    search: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    // Locked by the running search, kept from one search to the next
+    tt: Arc<Mutex<TranspositionTable>>,
+    pruning: Pruning,
+}
+
+impl State {
+    // End the running search, if any, which then prints its bestmove
+    fn stop(&mut self) {
+        if let Some((stop, handle)) = self.search.take() {
+            stop.store(true, Ordering::Relaxed);
+            let _ = handle.join();
+        }
+    }
+}
+
+// Talk the Universal Chess Interface on stdin and stdout until quit or the end of the input
+pub fn run() {
+    let tt = Arc::new(Mutex::new(TranspositionTable::new(DEFAULT_MB)));
+    let mut state = State {position: Position::new(), history: Vec::new(), search: None, tt, pruning: Pruning::default()};
+    state.history.push(state.position.key());
+
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {break};
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.first().copied() {
+            Some("uci") => {
+                println!("id name {}", NAME);
+                println!("id author {}", AUTHOR);
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_MB, MAX_MB);
+                for name in Pruning::NAMES {
+                    println!("option name {} type check default true", name);
+                }
+                println!("uciok");
+            }
+            Some("isready")    => println!("readyok"),
+            Some("ucinewgame") => {
+                state.stop();
+                state.position = Position::new();
+                state.history = vec![state.position.key()];
+                state.tt.lock().unwrap().clear();
+            }
+            Some("position") => {
+                state.stop();
+                match parse_position(&tokens[1..]) {
+                    Ok((position, history)) => {
+                        state.position = position;
+                        state.history = history;
+                    }
+                    Err(error) => println!("info string {}", error),
+                }
+            }
+            Some("go") => {
+                state.stop();
+                let limits = parse_go(&tokens[1..]);
+                let stop = Arc::new(AtomicBool::new(false));
+                let (position, history, flag, tt, pruning) = (state.position, state.history.clone(), stop.clone(), state.tt.clone(), state.pruning);
+                let handle = thread::spawn(move || search(position, history, &tt, pruning, limits, flag));
+                state.search = Some((stop, handle));
+            }
+            Some("stop")      => state.stop(),
+            Some("setoption") => {
+                state.stop();
+                set_option(&mut state, &tokens[1..]);
+            }
+            Some("quit")      => break,
+            _                 => {}
+        }
+    }
+
+    state.stop();
+}
+
+// Position and history described by the arguments of a position command:
+// startpos or fen <record>, then optionally moves <move>... in long coordinates
+fn parse_position(args: &[&str]) -> Result<(Position, Vec<PositionKey>), String> {
+    let moves_at = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+    let mut position = match args.first() {
+        Some(&"startpos") => Position::new(),
+        Some(&"fen")      => fen::parse(&args[1..moves_at].join(" ")).map_err(|error| format!("invalid FEN: {}", error))?,
+        _                 => return Err("position needs startpos or fen".to_string()),
+    };
+
+    let mut history = vec![position.key()];
+    for &text in args.iter().skip(moves_at + 1) {
+        let m = position.legal_moves().into_iter().find(|m| m.to_string() == text).ok_or(format!("illegal move {}", text))?;
+        position.make_move(m);
+        position.add_to_history(&mut history);
+    }
+    Ok((position, history))
+}
+
+fn parse_go(args: &[&str]) -> Limits {
+    let mut limits = Limits::default();
+    let value = |i: usize| args.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+
+    for (i, &arg) in args.iter().enumerate() {
+        match arg {
+            "depth"     => limits.depth = value(i).map(|d| d as usize),
+            "movetime"  => limits.movetime = value(i),
+            "wtime"     => limits.time[0] = value(i),
+            "btime"     => limits.time[1] = value(i),
+            "winc"      => limits.increment[0] = value(i).unwrap_or(0),
+            "binc"      => limits.increment[1] = value(i).unwrap_or(0),
+            "movestogo" => limits.movestogo = value(i).map(|n| n as u32),
+            "infinite"  => limits.infinite = true,
+            _           => {}
+        }
+    }
+    limits
+}
+
+// setoption name <name> value <value>, unknown options are reported rather than ignored silently
+fn set_option(state: &mut State, args: &[&str]) {
+    let name_at = args.iter().position(|&arg| arg == "name").map_or(0, |i| i + 1);
+    let value_at = args.iter().position(|&arg| arg == "value").unwrap_or(args.len());
+    let name = args[name_at.min(value_at)..value_at].join(" ");
+    let value = args.get(value_at + 1..).unwrap_or_default().join(" ");
+
+    match name.to_lowercase().as_str() {
+        "hash" => match value.parse::<usize>() {
+            Ok(mb) if (1..=MAX_MB).contains(&mb) => state.tt.lock().unwrap().resize(mb),
+            _ => println!("info string invalid Hash value {}", value),
+        },
+        _ => match (state.pruning.switch(&name), value.as_str()) {
+            (Some(switch), "true")  => *switch = true,
+            (Some(switch), "false") => *switch = false,
+            (Some(_), _)            => println!("info string invalid {} value {}", name, value),
+            (None, _)               => println!("info string unknown option {}", name),
+        },
+    }
+}
+
+// Time to spend on this move: the movetime asked, else a share of the clock of the side to move
+fn move_time(limits: &Limits, turn: bool) -> Option<Duration> {
+    if let Some(movetime) = limits.movetime {
+        return Some(Duration::from_millis(movetime));
+    }
+    let time = limits.time[turn as usize]?;
+    Some(time_budget(Duration::from_millis(time), Duration::from_millis(limits.increment[turn as usize]), limits.movestogo))
+}
+
+// Think within the limits of a go command and give the best move
+fn search(mut position: Position, history: Vec<PositionKey>, tt: &Mutex<TranspositionTable>, pruning: Pruning, limits: Limits, stop: Arc<AtomicBool>) {
+    let start = Instant::now();
+    let deadline = if limits.infinite {None} else {move_time(&limits, position.turn).map(|budget| start + budget)};
+    let max_depth = if limits.infinite {MAX_DEPTH} else {limits.depth.unwrap_or(MAX_DEPTH)};
+
+    let mut tt = tt.lock().unwrap();
+    let mut context = SearchContext::new(&history, &mut tt);
+    context.stop = stop.clone();
+    context.deadline = deadline;
+    context.pruning = pruning;
+    let best_move = think(&mut position, &mut context, max_depth, |depth, context| {
+        let millis = start.elapsed().as_millis().max(1);
+        println!("info depth {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+            depth, uci_score(context.score), context.nodes, context.nodes as u128 * 1000 / millis, context.tt.hashfull(), millis, context.pv_string());
+    });
+
+    // In infinite mode the best move waits for the stop command
+    while limits.infinite && !stop.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    match best_move {
+        Some(m) => println!("bestmove {}", m),
+        None    => println!("bestmove 0000"),
+    }
+}
+
+// Score in centipawns, or in moves to mate: the mate found at ply p scores MATE_SCORE - p - 1
+fn uci_score(score: isize) -> String {
+    if is_mate_score(score) {
+        let plies = MATE_SCORE - score.abs() - 1;
+        if score > 0 {format!("mate {}", (plies + 1) / 2)} else {format!("mate -{}", plies / 2)}
+    } else {
+        format!("cp {}", score)
+    }
+}
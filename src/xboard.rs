@@ -0,0 +1,321 @@
+use std::io::{self, BufRead};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fen;
+use crate::game::{parse_seconds, think, time_budget, Pruning, SearchContext, MAX_DEPTH};
+use crate::moves::Move;
+use crate::pgn;
+use crate::position::*;
+use crate::tt::{TranspositionTable, DEFAULT_MB, MAX_MB};
+use crate::uci::NAME;
+
+// What the main loop waits for: a line from the GUI, or the move of the search started as the given generation
+enum Event {
+    Command(String),
+    Done(u64, Option<Move>),
+}
+
+// The engine as the GUI drives it
+struct Engine {
+    // Every position of the game, the current one last, so moves can be taken back
+    positions: Vec<Position>,
+    // Side the engine plays, None in force mode where it only follows the moves it is given
+    engine_side: Option<bool>,
+    max_depth: usize,
+    // Time control set by level: moves per control, 0 for the whole game, and increment
+    moves_per_control: u32,
+    increment: Duration,
+    // Fixed time per move set by st
+    move_time: Option<Duration>,
+    // Engine's clock, as last told by time
+    clock: Duration,
+    post: bool,
+    pruning: Pruning,
+    // Generation and stop flag of the running search
+    search: Option<(u64, Arc<AtomicBool>)>,
+    generation: u64,
+    events: Sender<Event>,
+    // Locked by the running search, kept from one search to the next
+    tt: Arc<Mutex<TranspositionTable>>,
+}
+
+// Talk the Chess Engine Communication Protocol (XBoard/WinBoard) on stdin and stdout until quit or the end of the input
+pub fn run() {
+    let (events, receiver) = mpsc::channel();
+
+    let input = events.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {break};
+            if input.send(Event::Command(line)).is_err() {
+                return;
+            }
+        }
+        let _ = input.send(Event::Command("quit".to_string()));
+    });
+
+    let mut engine = Engine {
+        positions: vec![Position::new()],
+        engine_side: Some(true),
+        max_depth: MAX_DEPTH,
+        moves_per_control: 0,
+        increment: Duration::ZERO,
+        move_time: None,
+        clock: Duration::from_secs(300),
+        post: false,
+        pruning: Pruning::default(),
+        search: None,
+        generation: 0,
+        events,
+        tt: Arc::new(Mutex::new(TranspositionTable::new(DEFAULT_MB))),
+    };
+
+    for event in receiver {
+        match event {
+            Event::Command(line) => {
+                if !engine.command(&line) {
+                    break;
+                }
+            }
+            Event::Done(generation, m) => engine.finish(generation, m),
+        }
+    }
+    engine.stop();
+}
+
+impl Engine {
+    fn position(&self) -> Position {
+        *self.positions.last().expect("a game has at least one position")
+    }
+
+    // Positions since the last capture or pawn move, the current one last
+    fn history(&self) -> Vec<PositionKey> {
+        let mut history = Vec::new();
+        for position in &self.positions {
+            position.add_to_history(&mut history);
+        }
+        history
+    }
+
+    // Handle a command of the GUI, false once it is time to quit
+    fn command(&mut self, line: &str) -> bool {
+        let (name, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let args = args.trim();
+
+        match name {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating" | "otim" | "" => {}
+            "protover" => {
+                println!("feature myname=\"{}\" ping=1 memory=1 setboard=1 usermove=1 playother=1 san=0 colors=0 analyze=0 sigint=0 sigterm=0 reuse=1", NAME);
+                for name in Pruning::NAMES {
+                    println!("feature option=\"{} -check 1\"", name);
+                }
+                println!("feature done=1");
+            }
+            "new" => {
+                self.stop();
+                self.positions = vec![Position::new()];
+                self.engine_side = Some(true);
+                self.max_depth = MAX_DEPTH;
+                self.move_time = None;
+                self.tt.lock().unwrap().clear();
+            }
+            "force" | "result" => {
+                self.stop();
+                self.engine_side = None;
+            }
+            "go" => {
+                self.stop();
+                self.engine_side = Some(self.position().turn);
+                self.start();
+            }
+            "playother" => {
+                self.stop();
+                self.engine_side = Some(!self.position().turn);
+            }
+            "usermove" => self.user_move(args),
+            "level" => self.level(args),
+            "st" => match parse_seconds(args) {
+                Some(move_time) => self.move_time = Some(move_time),
+                None            => println!("Error (bad time): {}", line),
+            },
+            "sd" => match args.parse() {
+                Ok(depth) => self.max_depth = depth,
+                Err(_)    => println!("Error (bad depth): {}", line),
+            },
+            // Centiseconds left on the engine's clock
+            "time" => match args.parse::<u64>().ok().and_then(|centiseconds| centiseconds.checked_mul(10)) {
+                Some(milliseconds) => self.clock = Duration::from_millis(milliseconds),
+                None               => println!("Error (bad time): {}", line),
+            },
+            "undo" | "remove" => {
+                self.stop();
+                let count = if name == "undo" {1} else {2};
+                for _ in 0..count {
+                    if self.positions.len() > 1 {
+                        self.positions.pop();
+                    }
+                }
+            }
+            "setboard" => {
+                self.stop();
+                match fen::parse(args) {
+                    Ok(position) => self.positions = vec![position],
+                    Err(error)   => println!("tellusererror Illegal position: {}", error),
+                }
+            }
+            // Megabytes the engine may use, all of them go to the transposition table up to the size UCI allows too
+            "memory" => match args.parse::<usize>() {
+                Ok(mb) => {
+                    self.stop();
+                    self.tt.lock().unwrap().resize(mb.clamp(1, MAX_MB));
+                }
+                Err(_) => println!("Error (bad memory): {}", line),
+            },
+            // option NAME=VALUE, for the options declared with the features
+            "option" => {
+                let (name, value) = args.split_once('=').unwrap_or((args, ""));
+                match (self.pruning.switch(name), value) {
+                    (Some(switch), "1") => *switch = true,
+                    (Some(switch), "0") => *switch = false,
+                    _                   => println!("Error (bad option): {}", args),
+                }
+            }
+            "ping" => println!("pong {}", args),
+            "post"   => self.post = true,
+            "nopost" => self.post = false,
+            // Move now: the search ends and plays what it has
+            "?" => {
+                if let Some((_, stop)) = &self.search {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+            "quit" => return false,
+            _ => println!("Error (unknown command): {}", name),
+        }
+        true
+    }
+
+    fn user_move(&mut self, text: &str) {
+        self.stop();
+        let mut position = self.position();
+        let Some(m) = position.legal_moves().into_iter().find(|m| m.to_string() == text) else {
+            println!("Illegal move: {}", text);
+            return;
+        };
+        position.make_move(m);
+        self.positions.push(position);
+
+        if !self.announce_result() && self.engine_side == Some(position.turn) {
+            self.start();
+        }
+    }
+
+    // level MPS BASE INC, with BASE in minutes or minutes:seconds and INC in seconds
+    fn level(&mut self, args: &str) {
+        let fields: Vec<&str> = args.split_whitespace().collect();
+        let [moves, base, increment] = fields[..] else {
+            println!("Error (bad level): {}", args);
+            return;
+        };
+        let Some(increment) = parse_seconds(increment) else {
+            println!("Error (bad time): {}", args);
+            return;
+        };
+        let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+        let (Ok(moves), Ok(minutes), Ok(seconds)) = (moves.parse(), minutes.parse::<u64>(), seconds.parse::<u64>()) else {
+            println!("Error (bad level): {}", args);
+            return;
+        };
+        let Some(base) = minutes.checked_mul(60).and_then(|minutes| minutes.checked_add(seconds)) else {
+            println!("Error (bad time): {}", args);
+            return;
+        };
+        self.moves_per_control = moves;
+        self.clock = Duration::from_secs(base);
+        self.increment = increment;
+        self.move_time = None;
+    }
+
+    // Start thinking on the current position in a thread of its own, the move comes back as an event
+    fn start(&mut self) {
+        let mut position = self.position();
+        let history = self.history();
+
+        let budget = match self.move_time {
+            Some(move_time) => move_time,
+            None => {
+                let moves_to_go = (self.moves_per_control > 0)
+                    .then(|| self.moves_per_control - (position.fullmove_number as u32 - 1) % self.moves_per_control);
+                time_budget(self.clock, self.increment, moves_to_go)
+            }
+        };
+        // A budget too long to add to the current instant leaves the search without a deadline
+        let deadline = Instant::now().checked_add(budget);
+
+        self.generation += 1;
+        let generation = self.generation;
+        let stop = Arc::new(AtomicBool::new(false));
+        self.search = Some((generation, stop.clone()));
+
+        let (max_depth, post, pruning, events, tt) = (self.max_depth, self.post, self.pruning, self.events.clone(), self.tt.clone());
+        thread::spawn(move || {
+            let start = Instant::now();
+            let mut tt = tt.lock().unwrap();
+            let mut context = SearchContext::new(&history, &mut tt);
+            context.stop = stop;
+            context.deadline = deadline;
+            context.pruning = pruning;
+            let best_move = think(&mut position, &mut context, max_depth, |depth, context| {
+                // Thinking output: ply, score, time in centiseconds, nodes and principal variation
+                if post {
+                    println!("{} {} {} {} {}", depth, context.score, start.elapsed().as_millis() / 10, context.nodes, context.pv_string());
+                }
+            });
+            let _ = events.send(Event::Done(generation, best_move));
+        });
+    }
+
+    // Drop the running search, whatever it finds is ignored
+    fn stop(&mut self) {
+        if let Some((_, stop)) = self.search.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn finish(&mut self, generation: u64, best_move: Option<Move>) {
+        if self.search.as_ref().map(|&(current, _)| current) != Some(generation) {
+            return;
+        }
+        self.search = None;
+
+        if let Some(m) = best_move {
+            let mut position = self.position();
+            position.make_move(m);
+            self.positions.push(position);
+            println!("move {}", m);
+            self.announce_result();
+        }
+    }
+
+    // Tell the GUI if the game is over, the engine then stops playing
+    fn announce_result(&mut self) -> bool {
+        let position = self.position();
+        let result = pgn::result(&position, &self.history());
+        if result == "*" {
+            return false;
+        }
+
+        let reason = match position.status(&self.history()) {
+            GameStatus::Checkmate => if position.turn {"White mates"} else {"Black mates"},
+            GameStatus::Stalemate => "Stalemate",
+            _                     => "Draw",
+        };
+        println!("{} {{{}}}", result, reason);
+        self.engine_side = None;
+        true
+    }
+}
@@ -0,0 +1,52 @@
+use crate::pieces::Piece;
+use crate::position::CastleRights;
+
+use strum::EnumCount;
+
+const PIECE_KEYS: usize = Piece::COUNT * 2 * 64;
+const SIDE: usize = PIECE_KEYS;
+const CASTLE: usize = SIDE + 1;
+const EN_PASSANT: usize = CASTLE + 4;
+const KEY_COUNT: usize = EN_PASSANT + 8;
+
+// Random keys drawn at compile time from xorshift64*, with a fixed seed so hashes are the same on every build
+const KEYS: [u64; KEY_COUNT] = {
+    let mut keys = [0; KEY_COUNT];
+    let mut seed: u64 = 0x5EED_C0FF_EE15_600D;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        seed ^= seed >> 12;
+        seed ^= seed << 25;
+        seed ^= seed >> 27;
+        keys[i] = seed.wrapping_mul(2685821657736338717);
+        i += 1;
+    }
+    keys
+};
+
+// Key of the piece of bitboard `bitboard` (an index of Position::bitboards) standing on `index`
+pub fn piece(bitboard: usize, index: usize) -> u64 {
+    KEYS[bitboard * 64 + index]
+}
+
+// Key added when Black is to move
+pub fn side() -> u64 {
+    KEYS[SIDE]
+}
+
+// Keys of all the castling rights still there
+pub fn castle(rights: &CastleRights) -> u64 {
+    let mut key = 0;
+    for (color, sides) in rights.iter().enumerate() {
+        for (side, &right) in sides.iter().enumerate() {
+            if right {
+                key ^= KEYS[CASTLE + color * 2 + side];
+            }
+        }
+    }
+    key
+}
+
+pub fn en_passant(file: usize) -> u64 {
+    KEYS[EN_PASSANT + file]
+}